@@ -0,0 +1,271 @@
+use std::io::{self, Write};
+
+use half::f16;
+
+// the subset of OpenEXR 2.0 needed to store render output: a single part, uncompressed
+// scanline image with one scanline per chunk.
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+
+/// Storage type of every channel in the file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl ExrPixelType {
+    fn id(self) -> i32 {
+        match self {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float => 4,
+        }
+    }
+}
+
+/// One channel of `width * height` values, rows top to bottom. Layered channels use dotted
+/// names like `normal.X`.
+pub struct ExrChannel<'a> {
+    pub name: &'a str,
+    pub data: &'a [f32],
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: u32, height: u32) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+/// Writes `channels` as an uncompressed OpenEXR image. `chromaticities` are the xy
+/// coordinates of the red, green and blue primaries and the white point, readers assume
+/// rec.709 when they're missing.
+pub fn write_exr(
+    mut w: impl Write,
+    width: u32,
+    height: u32,
+    channels: &[ExrChannel],
+    pixel_type: ExrPixelType,
+    chromaticities: Option<[f32; 8]>,
+) -> io::Result<()> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    if width == 0 || height == 0 {
+        return Err(invalid("exr images can't be empty"));
+    }
+    let pixels = (width * height) as usize;
+    if channels.iter().any(|c| c.data.len() != pixels) {
+        return Err(invalid("channel size doesn't match the image"));
+    }
+    if channels
+        .iter()
+        .any(|c| c.name.is_empty() || c.name.contains('\0'))
+    {
+        return Err(invalid("bad channel name"));
+    }
+
+    // readers expect the channel list in alphabetical order, the data follows it.
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by_key(|c| c.name);
+    if channels.windows(2).any(|c| c[0].name == c[1].name) {
+        return Err(invalid("duplicate channel name"));
+    }
+
+    let mut chlist = vec![];
+    for c in channels.iter() {
+        chlist.extend_from_slice(c.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&pixel_type.id().to_le_bytes());
+        // pLinear and reserved bytes
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        // x and y sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+
+    let mut header = vec![];
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    attribute(&mut header, "channels", "chlist", &chlist);
+    if let Some(xy) = chromaticities {
+        let value: Vec<u8> = xy.iter().flat_map(|v| v.to_le_bytes()).collect();
+        attribute(&mut header, "chromaticities", "chromaticities", &value);
+    }
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &box2i(width, height));
+    attribute(&mut header, "displayWindow", "box2i", &box2i(width, height));
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let line_size = width as usize * channels.len() * pixel_type.size();
+    // y, data size and the data of each chunk.
+    let chunk_size = 8 + line_size;
+    let table_size = 8 * height as usize;
+
+    w.write_all(&header)?;
+    for y in 0..height as usize {
+        let offset = header.len() + table_size + y * chunk_size;
+        w.write_all(&(offset as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height as usize {
+        line.clear();
+        for c in channels.iter() {
+            let row = &c.data[y * width as usize..(y + 1) * width as usize];
+            for v in row {
+                match pixel_type {
+                    ExrPixelType::Half => line.extend_from_slice(&f16::from_f32(*v).to_le_bytes()),
+                    ExrPixelType::Float => line.extend_from_slice(&v.to_le_bytes()),
+                }
+            }
+        }
+
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+        w.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i32(b: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_layout() {
+        let r = [0.0, 0.25, 1.5, 100.0, -2.0, 65504.0];
+        let g = [1.0; 6];
+
+        for pixel_type in [ExrPixelType::Half, ExrPixelType::Float] {
+            let mut out = vec![];
+            // given out of order, stored as G, R.
+            let channels = [
+                ExrChannel {
+                    name: "R",
+                    data: &r,
+                },
+                ExrChannel {
+                    name: "G",
+                    data: &g,
+                },
+            ];
+            write_exr(&mut out, 3, 2, &channels, pixel_type, None).unwrap();
+
+            assert_eq!(out[0..4], MAGIC);
+            let chlist = out.windows(6).position(|w| w == b"chlist").unwrap();
+            assert_eq!(&out[chlist + 11..chlist + 13], b"G\0");
+
+            // the chunks follow the offset table, which follows the header.
+            let line_size = 3 * 2 * pixel_type.size();
+            let table = out.len() - 2 * (8 + line_size) - 16;
+            let offset = |i: usize| {
+                u64::from_le_bytes(out[table + 8 * i..table + 8 * i + 8].try_into().unwrap())
+            };
+            assert_eq!(offset(0) as usize, table + 16);
+
+            let chunk = offset(1) as usize;
+            assert_eq!(read_i32(&out, chunk), 1);
+            assert_eq!(read_i32(&out, chunk + 4) as usize, line_size);
+
+            let red = chunk + 8 + 3 * pixel_type.size();
+            let values: Vec<f32> = (0..3)
+                .map(|i| {
+                    let at = red + i * pixel_type.size();
+                    match pixel_type {
+                        ExrPixelType::Half => f16::from_le_bytes([out[at], out[at + 1]]).to_f32(),
+                        ExrPixelType::Float => {
+                            f32::from_le_bytes(out[at..at + 4].try_into().unwrap())
+                        }
+                    }
+                })
+                .collect();
+
+            assert_eq!(values, r[3..6]);
+        }
+    }
+
+    #[test]
+    fn test_rejects_mismatched_channels() {
+        let r = [0.0; 5];
+        let channels = [ExrChannel {
+            name: "R",
+            data: &r,
+        }];
+        assert!(write_exr(vec![], 3, 2, &channels, ExrPixelType::Half, None).is_err());
+    }
+
+    // what we write can be read back as a texture.
+    #[test]
+    fn test_reads_back_as_texture() {
+        let r = [0.0, 0.25, 1.5, 100.0, -2.0, 0.5];
+        let g = [1.0; 6];
+        let b = [0.125; 6];
+        let a = [0.75; 6];
+
+        for pixel_type in [ExrPixelType::Half, ExrPixelType::Float] {
+            let mut out = vec![];
+            let channels = [
+                ExrChannel {
+                    name: "A",
+                    data: &a,
+                },
+                ExrChannel {
+                    name: "R",
+                    data: &r,
+                },
+                ExrChannel {
+                    name: "G",
+                    data: &g,
+                },
+                ExrChannel {
+                    name: "B",
+                    data: &b,
+                },
+            ];
+            write_exr(&mut out, 3, 2, &channels, pixel_type, None).unwrap();
+
+            let image = rt_impl::texture_file::decode_exr(&out).unwrap();
+            assert_eq!((image.width, image.height), (3, 2));
+            for (i, t) in image.texels.iter().enumerate() {
+                assert_eq!(t.to_array(), [r[i], g[i], b[i]]);
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::exr::{write_exr, ExrChannel, ExrPixelType};
+use rayon::prelude::*;
+use rt_impl::{
+    aov::Aov,
+    color::{self, output_transform, srgb_encode},
+    tonemap::tonemap,
+    util::hash,
+    ShaderConstants,
+};
+use spirv_std::glam::{vec3, Vec3, Vec4, Vec4Swizzles};
+
+/// File formats an `Image` can be written as.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    // binary portable pixmap, handy for piping into other tools.
+    Ppm,
+    // linear radiance without any quantization, with depth in `Z`.
+    Exr(ExrPixelType),
+}
+
+impl ImageFormat {
+    /// guesses the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(Self::Png),
+            "ppm" => Some(Self::Ppm),
+            "exr" => Some(Self::Exr(ExrPixelType::Half)),
+            _ => None,
+        }
+    }
+}
+
+/// Rendered frame, rows are stored top to bottom.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    // linear radiance, alpha is always 1.
+    pub color: Vec<Vec4>,
+    // distance from the camera to the first hit, 100 where the ray escaped.
+    pub depth: Vec<f32>,
+    pub aovs: Vec<AovBuffer>,
+    // what the image was rendered with, the tone mapping fields can be changed to regrade
+    // the 8 bit output without rendering again.
+    pub settings: ShaderConstants,
+}
+
+/// One aov of a rendered frame, a plane per channel in the order of `Aov::channels`.
+#[derive(Clone, Debug)]
+pub struct AovBuffer {
+    pub aov: Aov,
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl AovBuffer {
+    /// 8 bit rgb for looking at the buffer, ranges are remapped so they are visible.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let pixels = self.channels[0].len();
+        let value = |c: usize, i: usize| self.channels[c.min(self.channels.len() - 1)][i];
+
+        // the range of the finite values in all channels.
+        let (lo, hi) = self
+            .channels
+            .iter()
+            .flatten()
+            .filter(|v| v.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+                (lo.min(*v), hi.max(*v))
+            });
+        let normalize = |v: f32| {
+            if !v.is_finite() || hi <= lo {
+                0.0
+            } else {
+                (v - lo) / (hi - lo)
+            }
+        };
+
+        (0..pixels)
+            .flat_map(|i| {
+                let v = vec3(value(0, i), value(1, i), value(2, i));
+                let d = match self.aov {
+                    Aov::Normal => v * 0.5 + 0.5,
+                    Aov::Albedo => srgb_encode(v),
+                    Aov::Depth | Aov::Position => {
+                        vec3(normalize(v.x), normalize(v.y), normalize(v.z))
+                    }
+                    Aov::SampleCount => heatmap(normalize(v.x)),
+                    // a random color per id, black where nothing was hit.
+                    Aov::MaterialId | Aov::ObjectId => {
+                        if v.x < 0.0 {
+                            Vec3::ZERO
+                        } else {
+                            let h = hash(v.x as u32 + 1);
+                            vec3(
+                                (h & 0xff) as f32,
+                                (h >> 8 & 0xff) as f32,
+                                (h >> 16 & 0xff) as f32,
+                            ) / 255.0
+                        }
+                    }
+                };
+                [
+                    (d.x * 255.999) as u8,
+                    (d.y * 255.999) as u8,
+                    (d.z * 255.999) as u8,
+                ]
+            })
+            .collect()
+    }
+}
+
+// black through blue, red and yellow to white for `t` in [0, 1].
+fn heatmap(t: f32) -> Vec3 {
+    const STOPS: [Vec3; 5] = [
+        Vec3::ZERO,
+        Vec3::new(0.1, 0.1, 0.8),
+        Vec3::new(0.9, 0.1, 0.2),
+        Vec3::new(1.0, 0.9, 0.1),
+        Vec3::ONE,
+    ];
+
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (x as usize).min(STOPS.len() - 2);
+    STOPS[i].lerp(STOPS[i + 1], x - i as f32)
+}
+
+/// `output.png` becomes `output.normal.png` for the normal aov.
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, aov.name(), ext.to_string_lossy()),
+        None => format!("{}.{}", stem, aov.name()),
+    };
+    path.with_file_name(name)
+}
+
+// cICP values, colour primaries, transfer characteristics, matrix and full range, from
+// ITU-T H.273.
+fn cicp(display: u32) -> Option<[u8; 4]> {
+    match display {
+        color::DISPLAY_REC709 => Some([1, 1, 0, 1]),
+        color::DISPLAY_P3 => Some([12, 13, 0, 1]),
+        // there is no code for a pure power curve, gAMA and cHRM describe it on their own.
+        color::DISPLAY_GAMMA2 => None,
+        _ => Some([1, 13, 0, 1]),
+    }
+}
+
+fn png_chromaticities(xy: color::Chromaticities) -> png::SourceChromaticities {
+    png::SourceChromaticities::new(
+        (xy[6], xy[7]),
+        (xy[0], xy[1]),
+        (xy[2], xy[3]),
+        (xy[4], xy[5]),
+    )
+}
+
+// `display` is one of `color::DISPLAY_*` and says how `data` is encoded, png output gets
+// tagged with it so viewers don't have to guess.
+fn write_rgb8(
+    mut w: impl Write,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    format: ImageFormat,
+    display: u32,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(w, width, height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            match display {
+                color::DISPLAY_SRGB => encoder.set_srgb(png::SrgbRenderingIntent::Perceptual),
+                color::DISPLAY_P3 => {
+                    encoder.set_source_chromaticities(png_chromaticities(
+                        color::P3_D65_CHROMATICITIES,
+                    ));
+                    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+                }
+                color::DISPLAY_GAMMA2 => {
+                    encoder.set_source_chromaticities(png_chromaticities(
+                        color::REC709_CHROMATICITIES,
+                    ));
+                    encoder.set_source_gamma(png::ScaledFloat::new(0.5));
+                }
+                _ => {
+                    encoder.set_source_chromaticities(png_chromaticities(
+                        color::REC709_CHROMATICITIES,
+                    ));
+                }
+            }
+
+            let mut writer = encoder.write_header()?;
+            // cICP has to come before the image data, readers that know it prefer it over
+            // the older chunks.
+            if let Some(cicp) = cicp(display) {
+                writer.write_chunk(png::chunk::ChunkType(*b"cICP"), &cicp)?;
+            }
+            writer.write_image_data(data)?;
+        }
+        ImageFormat::Ppm => {
+            write!(w, "P6\n{} {}\n255\n", width, height)?;
+            w.write_all(data)?;
+        }
+        ImageFormat::Exr(_) => unreachable!("exr is written from the float buffers"),
+    }
+
+    Ok(())
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> Vec4 {
+        self.color[(y * self.width + x) as usize]
+    }
+
+    /// tone mapped 8 bit rgb, encoded for `settings.display`.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.color
+            .par_iter()
+            .flat_map_iter(|c| {
+                let d = output_transform(&self.settings, tonemap(&self.settings, c.xyz()));
+                [
+                    (d.x * 255.999) as u8,
+                    (d.y * 255.999) as u8,
+                    (d.z * 255.999) as u8,
+                ]
+            })
+            .collect()
+    }
+
+    pub fn aov(&self, aov: Aov) -> Option<&AovBuffer> {
+        self.aovs.iter().find(|b| b.aov == aov)
+    }
+
+    /// Writes the image to `path`. Exr files get the aovs as extra layers, the 8 bit
+    /// formats write each aov to its own file next to it, see `aov_path`.
+    pub fn save(&self, path: &Path, format: ImageFormat) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?), format)?;
+
+        if !matches!(format, ImageFormat::Exr(_)) {
+            for b in self.aovs.iter() {
+                let w = BufWriter::new(File::create(aov_path(path, b.aov))?);
+                write_rgb8(
+                    w,
+                    self.width,
+                    self.height,
+                    &b.to_rgb8(),
+                    format,
+                    color::DISPLAY_SRGB,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the beauty, and for exr the aovs, to `w`.
+    pub fn write(&self, w: impl Write, format: ImageFormat) -> io::Result<()> {
+        match format {
+            ImageFormat::Exr(pixel_type) => self.write_exr(w, pixel_type),
+            _ => write_rgb8(
+                w,
+                self.width,
+                self.height,
+                &self.to_rgb8(),
+                format,
+                self.settings.display,
+            ),
+        }
+    }
+
+    fn write_exr(&self, w: impl Write, pixel_type: ExrPixelType) -> io::Result<()> {
+        let channel = |i: usize| -> Vec<f32> { self.color.iter().map(|c| c[i]).collect() };
+        let (r, g, b, a) = (channel(0), channel(1), channel(2), channel(3));
+
+        let mut channels = vec![
+            ExrChannel {
+                name: "R",
+                data: &r,
+            },
+            ExrChannel {
+                name: "G",
+                data: &g,
+            },
+            ExrChannel {
+                name: "B",
+                data: &b,
+            },
+            ExrChannel {
+                name: "A",
+                data: &a,
+            },
+            ExrChannel {
+                name: "Z",
+                data: &self.depth,
+            },
+        ];
+
+        let names: Vec<Vec<String>> = self
+            .aovs
+            .iter()
+            .map(|b| {
+                b.aov
+                    .channels()
+                    .iter()
+                    .map(|c| format!("{}.{}", b.aov.name(), c))
+                    .collect()
+            })
+            .collect();
+
+        for (b, names) in self.aovs.iter().zip(names.iter()) {
+            for (data, name) in b.channels.iter().zip(names) {
+                channels.push(ExrChannel { name, data });
+            }
+        }
+
+        write_exr(
+            w,
+            self.width,
+            self.height,
+            &channels,
+            pixel_type,
+            Some(color::working_chromaticities(&self.settings)),
+        )
+    }
+}
@@ -1,61 +1,339 @@
 use itertools::Itertools;
 use rayon::prelude::*;
 use rt_impl::{
-    depth::{self, render_depth_pass},
-    describe_scene, render_pass_one, ShaderConstants,
+    aov::{Aov, AovPixel},
+    denoise::{atrous_step, demodulate, remodulate, ATROUS_ITERATIONS, DENOISE_NONE},
+    depth::render_post_pass,
+    Scene, ShaderConstants,
 };
-use std::{fs::File, io::BufWriter};
+use std::fmt;
 
-use spirv_std::glam::{uvec2, UVec2, Vec4};
+use spirv_std::glam::{uvec2, Vec3, Vec4, Vec4Swizzles};
 
-pub fn render_cpu(wh: UVec2) {
-    println!("Rendering on CPU with width, height: {}, {}", wh.x, wh.y);
+mod exr;
+mod image;
+mod progressive;
 
-    let file = File::create("output.png").unwrap();
-    let w = &mut BufWriter::new(file);
-    let mut encoder = png::Encoder::new(w, wh.x, wh.y); // Width is 2 pixels and height is 1.
-    encoder.set_color(png::ColorType::Rgb);
-    encoder.set_depth(png::BitDepth::Eight);
+pub use exr::{write_exr, ExrChannel, ExrPixelType};
+pub use image::{aov_path, AovBuffer, Image, ImageFormat};
+pub use progressive::{render_progressive, Progress, ProgressiveRender, ProgressiveSettings};
 
-    let mut writer = encoder.write_header().unwrap();
+#[derive(Debug)]
+pub enum RenderError {
+    InvalidSettings(&'static str),
+}
 
-    let c = ShaderConstants {
-        width: wh.x,
-        height: wh.y,
-        aa_stages: 100,
-        bounce_limit: 100,
-        focus_point: 78.0,
-    };
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::InvalidSettings(message) => write!(f, "invalid settings: {}", message),
+        }
+    }
+}
 
-    let world = describe_scene();
+impl std::error::Error for RenderError {}
 
-    let iter: Vec<(u32, u32)> = (0..wh.y)
-        .into_iter()
-        .cartesian_product(0..wh.x)
-        .into_iter()
-        .collect::<Vec<(u32, u32)>>();
+/// Renders `scene` on the cpu with rayon's global thread pool. The result is linear and
+/// has the post process depth of field applied when `sc.dof` asks for it.
+pub fn render(scene: &Scene, sc: &ShaderConstants) -> Result<Image, RenderError> {
+    render_aovs(scene, sc, &[])
+}
 
-    let pass_one: Vec<Vec4> = iter
-        .par_iter()
-        .map(|(h, w)| render_pass_one(&c, &world, uvec2(*w, *h)))
+// runs the a-trous passes over the radiance in `pass_one`, leaving the depth in w alone.
+fn denoise(sc: &ShaderConstants, iter: &[(u32, u32)], pass_one: &mut [Vec4], guide: &[AovPixel]) {
+    let mut buf: Vec<Vec3> = pass_one
+        .iter()
+        .zip(guide)
+        .map(|(c, g)| demodulate(c.xyz(), g))
         .collect();
 
-    let depth_pass: Vec<Vec4> = iter
-        .par_iter()
-        .map(|(h, w)| render_depth_pass(&c, &world, uvec2(*w, *h), &pass_one))
+    for iteration in 0..ATROUS_ITERATIONS {
+        buf = iter
+            .par_iter()
+            .map(|(h, w)| atrous_step(sc, uvec2(*w, *h), iteration, &buf, guide))
+            .collect();
+    }
+
+    for ((c, b), g) in pass_one.iter_mut().zip(buf).zip(guide) {
+        *c = remodulate(b, g).extend(c.w);
+    }
+}
+
+/// `render` that also fills in the requested aovs.
+pub fn render_aovs(
+    scene: &Scene,
+    sc: &ShaderConstants,
+    aovs: &[Aov],
+) -> Result<Image, RenderError> {
+    let mut r = ProgressiveRender::new(scene, sc, aovs)?;
+    r.render_pass(sc.aa_stages);
+    Ok(r.image())
+}
+
+// denoises and post processes the averaged first pass into the final image.
+pub(crate) fn finish_image(
+    sc: &ShaderConstants,
+    aovs: &[Aov],
+    mut pass_one: Vec<Vec4>,
+    aov_pixels: &[AovPixel],
+) -> Image {
+    let iter: Vec<(u32, u32)> = (0..sc.height)
+        .cartesian_product(0..sc.width)
+        .collect::<Vec<(u32, u32)>>();
+
+    let aovs = aovs
+        .iter()
+        .map(|aov| AovBuffer {
+            aov: *aov,
+            channels: (0..aov.channels().len())
+                .map(|c| aov_pixels.iter().map(|p| p.get(*aov)[c]).collect())
+                .collect(),
+        })
         .collect();
 
-    let data: Vec<u8> = depth_pass
+    if sc.denoise != DENOISE_NONE {
+        denoise(sc, &iter, &mut pass_one, aov_pixels);
+    }
+
+    let color: Vec<Vec4> = iter
         .par_iter()
-        .map(|d| {
-            vec![
-                (d.x * 255.999) as u8,
-                (d.y * 255.999) as u8,
-                (d.z * 255.999) as u8,
-            ]
-        })
-        .flatten()
+        .map(|(h, w)| render_post_pass(sc, uvec2(*w, *h), &pass_one).extend(1.0))
         .collect();
 
-    writer.write_image_data(&data).unwrap();
+    Image {
+        width: sc.width,
+        height: sc.height,
+        color,
+        depth: pass_one.iter().map(|p| p.w).collect(),
+        aovs,
+        settings: *sc,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rt_impl::{
+        camera::Camera,
+        color::DISPLAY_GAMMA2,
+        denoise::DENOISE_ATROUS,
+        depth::DOF_NONE,
+        describe_cornell_box, describe_scene2,
+        hittable::{HittableE, Sphere},
+        material::{DiffuseLightMaterial, LambertianMaterial, MaterialE},
+        mesh::Mesh,
+        texture::{CheckerTexture, TextureE, CHECKER_UV},
+    };
+    use spirv_std::glam::vec3;
+
+    fn settings() -> ShaderConstants {
+        ShaderConstants {
+            width: 8,
+            height: 4,
+            aa_stages: 1,
+            bounce_limit: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_returns_image() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        let image = render(&scene, &settings()).unwrap();
+
+        assert_eq!(image.color.len(), 32);
+        assert_eq!(image.depth.len(), 32);
+        assert!(image.color.iter().all(|c| c.is_finite() && c.w == 1.0));
+        assert!(image.depth.iter().all(|d| *d > 0.0));
+
+        let mut png = vec![];
+        image.write(&mut png, ImageFormat::Png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert!(png.windows(4).any(|w| w == b"sRGB"));
+        assert!(png.windows(8).any(|w| w == b"cICP\x01\x0d\x00\x01"));
+
+        // a square root has no cICP code, only gAMA and cHRM describe it.
+        let sc = ShaderConstants {
+            display: DISPLAY_GAMMA2,
+            ..settings()
+        };
+        let mut png = vec![];
+        render(&scene, &sc)
+            .unwrap()
+            .write(&mut png, ImageFormat::Png)
+            .unwrap();
+        assert!(!png.windows(4).any(|w| w == b"cICP"));
+        assert!(png.windows(4).any(|w| w == b"gAMA"));
+        assert!(png.windows(4).any(|w| w == b"cHRM"));
+    }
+
+    #[test]
+    fn test_light_seen_directly() {
+        // the camera sits inside a light, so every camera ray ends on it.
+        let radiance = vec3(4.0, 2.0, 1.0);
+        let light = MaterialE::DiffuseLight(DiffuseLightMaterial::new(radiance));
+        let world = HittableE::Sphere(Sphere::new(Vec3::ZERO, 100.0, light));
+        let scene = Scene::new(world, Camera::default());
+        let sc = ShaderConstants {
+            sky: 0,
+            dof: DOF_NONE,
+            ..settings()
+        };
+
+        let image = render(&scene, &sc).unwrap();
+        for c in image.color.iter() {
+            assert!(c.xyz().abs_diff_eq(radiance, 1e-4), "{c} isn't {radiance}");
+        }
+    }
+
+    #[test]
+    fn test_cornell_box_renders() {
+        let scene = Scene::new(describe_cornell_box(), Camera::default());
+        let sc = ShaderConstants {
+            width: 16,
+            height: 16,
+            aa_stages: 4,
+            sky: 0,
+            ..settings()
+        };
+
+        let image = render(&scene, &sc).unwrap();
+        assert!(image.color.iter().all(|c| c.is_finite()));
+        // lit only by the ceiling light, so anything but black means light reached the camera.
+        let lit = image.color.iter().filter(|c| c.xyz().max_element() > 0.0);
+        assert!(lit.count() > image.color.len() / 2);
+    }
+
+    #[test]
+    fn test_textured_light_matches_its_mean() {
+        let render_lit_by = |radiance: TextureE| {
+            let floor = MaterialE::Lambertian(LambertianMaterial::new(Vec3::splat(0.5)));
+            let light = MaterialE::DiffuseLight(DiffuseLightMaterial::new(radiance));
+            let world = HittableE::List(vec![
+                HittableE::Mesh(Mesh::quad(
+                    vec3(-50.0, -0.5, -50.0),
+                    vec3(100.0, 0.0, 0.0),
+                    vec3(0.0, 0.0, 100.0),
+                    floor,
+                )),
+                HittableE::Mesh(Mesh::quad(
+                    vec3(-2.0, 3.0, -3.0),
+                    vec3(4.0, 0.0, 0.0),
+                    vec3(0.0, 0.0, 4.0),
+                    light,
+                )),
+            ]);
+            let sc = ShaderConstants {
+                width: 16,
+                height: 8,
+                aa_stages: 64,
+                sky: 0,
+                dof: DOF_NONE,
+                ..settings()
+            };
+            let image = render(&Scene::new(world, Camera::default()), &sc).unwrap();
+            image.color.iter().map(|c| c.xyz()).sum::<Vec3>() / image.color.len() as f32
+        };
+
+        // small black and bright cells, averaging to the constant.
+        let checker = CheckerTexture::new(Vec3::ZERO, Vec3::splat(8.0), 0.05, CHECKER_UV);
+        let textured = render_lit_by(TextureE::Checker(checker));
+        let constant = render_lit_by(Vec3::splat(4.0).into());
+
+        assert!(constant.x > 0.0);
+        assert!(
+            (textured.x / constant.x - 1.0).abs() < 0.05,
+            "{textured} against {constant}"
+        );
+    }
+
+    #[test]
+    fn test_render_aovs() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        let sc = ShaderConstants {
+            aa_stages: 3,
+            ..settings()
+        };
+        let image = render_aovs(&scene, &sc, &[Aov::Normal, Aov::SampleCount]).unwrap();
+
+        assert_eq!(image.aovs.len(), 2);
+        let normal = image.aov(Aov::Normal).unwrap();
+        assert_eq!(normal.channels.len(), 3);
+        assert_eq!(normal.channels[0].len(), 32);
+
+        let samples = image.aov(Aov::SampleCount).unwrap();
+        assert!(samples.channels[0].iter().all(|n| *n == 3.0));
+
+        let mut exr = vec![];
+        image
+            .write(&mut exr, ImageFormat::Exr(ExrPixelType::Float))
+            .unwrap();
+        assert!(exr.windows(9).any(|w| w == b"normal.X\0"));
+    }
+
+    #[test]
+    fn test_denoise_reduces_error() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        // big enough that a pixel doesn't cover so much of the scene that the guides blur
+        // across every edge.
+        let sc = ShaderConstants {
+            width: 128,
+            height: 64,
+            aa_stages: 4,
+            dof: DOF_NONE,
+            ..settings()
+        };
+
+        let reference = render(
+            &scene,
+            &ShaderConstants {
+                aa_stages: 256,
+                seed: 1,
+                ..sc
+            },
+        )
+        .unwrap();
+        let noisy = render(&scene, &sc).unwrap();
+        let denoised = render(
+            &scene,
+            &ShaderConstants {
+                denoise: DENOISE_ATROUS,
+                ..sc
+            },
+        )
+        .unwrap();
+
+        // clamped so the odd firefly doesn't decide the result.
+        let error = |image: &Image| -> f32 {
+            image
+                .color
+                .iter()
+                .zip(reference.color.iter())
+                .map(|(a, b)| {
+                    let d = a.xyz().min(Vec3::ONE) - b.xyz().min(Vec3::ONE);
+                    d.length_squared()
+                })
+                .sum::<f32>()
+                / image.color.len() as f32
+        };
+
+        let (before, after) = (error(&noisy), error(&denoised));
+        assert!(after < before * 0.5, "error went from {before} to {after}");
+        // the depth used by the post pass is untouched.
+        assert_eq!(noisy.depth, denoised.depth);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        let sc = ShaderConstants {
+            width: 0,
+            ..settings()
+        };
+
+        assert!(matches!(
+            render(&scene, &sc),
+            Err(RenderError::InvalidSettings(_))
+        ));
+    }
 }
@@ -0,0 +1,517 @@
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use rayon::prelude::*;
+use rt_impl::{
+    aov::{Aov, AovPixel},
+    color::luminance,
+    denoise::DENOISE_NONE,
+    filter::{filter_reach, filter_weight},
+    render_sample, PixelSample, Scene, ShaderConstants,
+};
+use spirv_std::glam::{ivec2, uvec2, Vec4, Vec4Swizzles};
+
+use crate::{finish_image, Image, RenderError};
+
+// keeps the relative error of near black pixels from dominating the frame.
+const NOISE_FLOOR: f32 = 0.01;
+
+/// When `render_progressive` stops. It never takes more than `ShaderConstants::aa_stages`
+/// samples per pixel.
+#[derive(Copy, Clone, Debug)]
+pub struct ProgressiveSettings {
+    /// samples added to every pixel by each pass.
+    pub pass_samples: u32,
+    /// stop once `ProgressiveRender::noise` drops to this.
+    pub target_noise: Option<f32>,
+    /// checked after every pass, so the last pass can run over it.
+    pub time_limit: Option<Duration>,
+    /// Adaptive sampling, pixels stop taking samples once their noise and that of their
+    /// neighbours is at or below this.
+    pub adaptive_threshold: Option<f32>,
+    /// samples every pixel takes before adaptive sampling can stop it.
+    pub min_samples: u32,
+}
+
+impl Default for ProgressiveSettings {
+    fn default() -> Self {
+        Self {
+            pass_samples: 4,
+            target_noise: None,
+            time_limit: None,
+            adaptive_threshold: None,
+            min_samples: 16,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    pub passes: u32,
+    /// most samples taken by a pixel so far.
+    pub samples: u32,
+    pub mean_samples: f32,
+    /// pixels that will get samples in the next pass.
+    pub active: usize,
+    pub noise: f32,
+    pub elapsed: Duration,
+}
+
+// running sums of one pixel.
+#[derive(Copy, Clone, Default)]
+struct PixelState {
+    n: u32,
+    sum: Vec4,
+    // filter weighted radiance splatted in by this and the surrounding pixels' samples,
+    // with the sum of the weights in w.
+    film: Vec4,
+    // welford's running mean and sum of squared deviations of the sample luminance.
+    mean: f32,
+    m2: f32,
+    aov: AovPixel,
+}
+
+impl PixelState {
+    fn add(&mut self, sample: Vec4) {
+        self.n += 1;
+        self.sum += sample;
+
+        let l = luminance(sample.xyz());
+        let delta = l - self.mean;
+        self.mean += delta / self.n as f32;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    // standard error of the mean luminance relative to the mean.
+    fn noise(&self) -> f32 {
+        if self.n < 2 {
+            return f32::INFINITY;
+        }
+
+        let variance = self.m2 / (self.n - 1) as f32;
+        (variance / self.n as f32).sqrt() / (self.mean.abs() + NOISE_FLOOR)
+    }
+
+    // radiance through the reconstruction filter with the box filtered depth in w. A
+    // filter that stays inside the pixel gives every sample the same weight, that's just
+    // the mean. Negative lobes can leave a pixel with next to no weight early on, it's
+    // box filtered until the weight adds up.
+    fn color(&self, filtered: bool) -> Vec4 {
+        let mean = self.sum / self.n.max(1) as f32;
+        if !filtered || self.film.w < 1e-3 {
+            return mean;
+        }
+        (self.film.xyz() / self.film.w).extend(mean.w)
+    }
+}
+
+// adds `s`, taken in the pixel at `x` in row `y`, to the pixels around it. `splat` holds
+// the rows from `y - reach` to `y + reach`.
+fn splat_sample(sc: &ShaderConstants, splat: &mut [Vec4], x: u32, y: u32, s: &PixelSample) {
+    let reach = filter_reach(sc);
+    let (width, height) = (sc.width as i32, sc.height as i32);
+
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let q = ivec2(x as i32 + dx, y as i32 + dy);
+            if q.x < 0 || q.y < 0 || q.x >= width || q.y >= height {
+                continue;
+            }
+
+            let w = filter_weight(sc, s.offset - ivec2(dx, dy).as_vec2());
+            if w != 0.0 {
+                let i = ((dy + reach) * width + q.x) as usize;
+                splat[i] += (s.color.xyz() * w).extend(w);
+            }
+        }
+    }
+}
+
+/// A frame rendered in passes that each add samples to the active pixels, all of them
+/// unless `update_active` says otherwise. Sample indices carry on from one pass to the
+/// next, so a pixel only depends on how many samples it took.
+pub struct ProgressiveRender<'a> {
+    scene: &'a Scene,
+    sc: ShaderConstants,
+    aovs: Vec<Aov>,
+    pixels: Vec<PixelState>,
+    active: Vec<bool>,
+    passes: u32,
+    start: Instant,
+}
+
+impl<'a> ProgressiveRender<'a> {
+    pub fn new(scene: &'a Scene, sc: &ShaderConstants, aovs: &[Aov]) -> Result<Self, RenderError> {
+        if sc.width == 0 || sc.height == 0 {
+            return Err(RenderError::InvalidSettings(
+                "width and height must be positive",
+            ));
+        }
+        if sc.aa_stages == 0 {
+            return Err(RenderError::InvalidSettings("aa_stages must be positive"));
+        }
+
+        let len = (sc.width * sc.height) as usize;
+
+        Ok(Self {
+            scene,
+            sc: *sc,
+            aovs: aovs.to_vec(),
+            pixels: vec![PixelState::default(); len],
+            active: vec![true; len],
+            passes: 0,
+            start: Instant::now(),
+        })
+    }
+
+    /// adds `samples` samples to every active pixel, without going over
+    /// `ShaderConstants::aa_stages`.
+    pub fn render_pass(&mut self, samples: u32) {
+        let (sc, scene) = (&self.sc, self.scene);
+        // the denoiser is guided by the aovs, so they're needed even if none are written.
+        let wants_aovs = !self.aovs.is_empty() || sc.denoise != DENOISE_NONE;
+
+        let width = sc.width as usize;
+        let reach = filter_reach(sc);
+
+        // rendered a row at a time, each splatting into its own buffer, so the sums come
+        // out the same whatever order the rows finish in.
+        let splats: Vec<Vec<Vec4>> = self
+            .pixels
+            .par_chunks_mut(width)
+            .zip(self.active.par_chunks(width))
+            .enumerate()
+            .map(|(y, (row, active))| {
+                let mut splat = vec![Vec4::ZERO; (2 * reach as usize + 1) * width];
+                for (x, (p, _)) in row
+                    .iter_mut()
+                    .zip(active)
+                    .enumerate()
+                    .filter(|(_, (_, a))| **a)
+                {
+                    let first = p.n;
+                    for i in first..(first + samples).min(sc.aa_stages) {
+                        let aov = wants_aovs.then_some(&mut p.aov);
+                        let s = render_sample(sc, scene, uvec2(x as u32, y as u32), i, aov);
+                        p.add(s.color);
+                        splat_sample(sc, &mut splat, x as u32, y as u32, &s);
+                    }
+                }
+                splat
+            })
+            .collect();
+
+        self.pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for dy in -reach..=reach {
+                    // the row whose samples land `dy` rows below it, in this one.
+                    let source = y as i32 - dy;
+                    if source < 0 || source >= splats.len() as i32 {
+                        continue;
+                    }
+                    let from = &splats[source as usize][(dy + reach) as usize * width..][..width];
+                    for (p, s) in row.iter_mut().zip(from) {
+                        p.film += *s;
+                    }
+                }
+            });
+
+        self.passes += 1;
+        for (active, p) in self.active.iter_mut().zip(self.pixels.iter()) {
+            *active &= p.n < sc.aa_stages;
+        }
+    }
+
+    /// Stops sampling pixels whose noise is at or below `threshold` once they have
+    /// `min_samples`. A pixel keeps going while any of its 8 neighbours does, the noise
+    /// estimate of a single pixel is too easily fooled by a few lucky samples.
+    pub fn update_active(&mut self, threshold: f32, min_samples: u32) {
+        let (width, height) = (self.sc.width as i32, self.sc.height as i32);
+        let noisy: Vec<bool> = self
+            .pixels
+            .iter()
+            .map(|p| p.n < min_samples || p.noise() > threshold)
+            .collect();
+
+        for (i, active) in self.active.iter_mut().enumerate() {
+            if !*active {
+                continue;
+            }
+
+            let (x, y) = (i as i32 % width, i as i32 / width);
+            *active = (-1..=1).cartesian_product(-1..=1).any(|(dx, dy)| {
+                let (qx, qy) = (x + dx, y + dy);
+                qx >= 0 && qy >= 0 && qx < width && qy < height && noisy[(qy * width + qx) as usize]
+            });
+        }
+    }
+
+    /// most samples taken by a pixel.
+    pub fn samples(&self) -> u32 {
+        self.pixels.iter().map(|p| p.n).max().unwrap_or(0)
+    }
+
+    pub fn active_pixels(&self) -> usize {
+        self.active.iter().filter(|a| **a).count()
+    }
+
+    /// mean over the pixels of the standard error of their luminance relative to its
+    /// value, infinite until every pixel has two samples.
+    pub fn noise(&self) -> f32 {
+        let total: f32 = self.pixels.iter().map(|p| p.noise()).sum();
+        total / self.pixels.len() as f32
+    }
+
+    pub fn progress(&self) -> Progress {
+        let total: u64 = self.pixels.iter().map(|p| p.n as u64).sum();
+        Progress {
+            passes: self.passes,
+            samples: self.samples(),
+            mean_samples: total as f32 / self.pixels.len() as f32,
+            active: self.active_pixels(),
+            noise: self.noise(),
+            elapsed: self.start.elapsed(),
+        }
+    }
+
+    /// the frame as it is now, denoised and post processed like `render`'s output.
+    pub fn image(&self) -> Image {
+        let filtered = filter_reach(&self.sc) > 0;
+        let pass_one: Vec<Vec4> = self.pixels.iter().map(|p| p.color(filtered)).collect();
+        let aov_pixels: Vec<AovPixel> = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let mut a = p.aov;
+                a.finish();
+                a
+            })
+            .collect();
+
+        let sc = ShaderConstants {
+            aa_stages: self.samples(),
+            ..self.sc
+        };
+        finish_image(&sc, &self.aovs, pass_one, &aov_pixels)
+    }
+}
+
+/// Renders in passes of `settings.pass_samples` until every pixel has `sc.aa_stages`
+/// samples, or adaptive sampling has stopped it, or one of the other limits in `settings`
+/// is reached. `on_pass` is called after every pass,
+/// e.g. to report progress or write `ProgressiveRender::image` somewhere.
+pub fn render_progressive(
+    scene: &Scene,
+    sc: &ShaderConstants,
+    aovs: &[Aov],
+    settings: &ProgressiveSettings,
+    mut on_pass: impl FnMut(&ProgressiveRender),
+) -> Result<Image, RenderError> {
+    if settings.pass_samples == 0 {
+        return Err(RenderError::InvalidSettings(
+            "pass_samples must be positive",
+        ));
+    }
+
+    let mut r = ProgressiveRender::new(scene, sc, aovs)?;
+    loop {
+        r.render_pass(settings.pass_samples);
+        if let Some(threshold) = settings.adaptive_threshold {
+            r.update_active(threshold, settings.min_samples);
+        }
+        on_pass(&r);
+
+        let converged = settings.target_noise.is_some_and(|t| r.noise() <= t);
+        let out_of_time = settings.time_limit.is_some_and(|t| r.start.elapsed() >= t);
+        if r.active_pixels() == 0 || converged || out_of_time {
+            break;
+        }
+    }
+
+    Ok(r.image())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render;
+    use rt_impl::{
+        camera::Camera, depth::DOF_NONE, describe_scene2, filter::FILTER_MITCHELL, render_pixel,
+        sampler::SAMPLER_INDEPENDENT,
+    };
+    use spirv_std::glam::Vec3;
+
+    fn settings() -> ShaderConstants {
+        ShaderConstants {
+            width: 8,
+            height: 4,
+            aa_stages: 6,
+            bounce_limit: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_passes_match_single_render() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        let sc = ShaderConstants {
+            dof: DOF_NONE,
+            ..settings()
+        };
+
+        let mut passes = vec![];
+        let progressive =
+            render_progressive(&scene, &sc, &[], &ProgressiveSettings::default(), |r| {
+                passes.push(r.samples())
+            })
+            .unwrap();
+
+        assert_eq!(passes, vec![4, 6]);
+        // the same as taking every sample of a pixel in one go.
+        let expected: Vec<Vec4> = (0..sc.height)
+            .cartesian_product(0..sc.width)
+            .map(|(h, w)| {
+                render_pixel(&sc, &scene, uvec2(w, h), None)
+                    .xyz()
+                    .extend(1.0)
+            })
+            .collect();
+        assert_eq!(progressive.color, expected);
+    }
+
+    #[test]
+    fn test_filter_splats_into_neighbours() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        let sc = ShaderConstants {
+            dof: DOF_NONE,
+            filter: FILTER_MITCHELL,
+            ..settings()
+        };
+        let image =
+            render_progressive(&scene, &sc, &[], &ProgressiveSettings::default(), |_| {}).unwrap();
+
+        // every sample within the filter's reach, weighted by where it landed.
+        let reach = filter_reach(&sc);
+        let expected: Vec<Vec4> = (0..sc.height as i32)
+            .cartesian_product(0..sc.width as i32)
+            .map(|(h, w)| {
+                let mut sum = Vec4::ZERO;
+                for (dy, dx) in (-reach..=reach).cartesian_product(-reach..=reach) {
+                    let q = ivec2(w + dx, h + dy);
+                    if q.x < 0 || q.y < 0 || q.x >= sc.width as i32 || q.y >= sc.height as i32 {
+                        continue;
+                    }
+                    for i in 0..sc.aa_stages {
+                        let s = render_sample(&sc, &scene, q.as_uvec2(), i, None);
+                        let weight = filter_weight(&sc, s.offset + ivec2(dx, dy).as_vec2());
+                        sum += (s.color.xyz() * weight).extend(weight);
+                    }
+                }
+                (sum.xyz() / sum.w).extend(1.0)
+            })
+            .collect();
+
+        for (a, b) in image.color.iter().zip(expected) {
+            assert!((*a - b).abs().max_element() < 1e-4, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn test_stops_on_target_noise() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        let sc = ShaderConstants {
+            aa_stages: 64,
+            ..settings()
+        };
+
+        let mut noise = vec![];
+        let image = render_progressive(
+            &scene,
+            &sc,
+            &[],
+            &ProgressiveSettings {
+                pass_samples: 1,
+                target_noise: Some(0.1),
+                ..Default::default()
+            },
+            |r| noise.push(r.noise()),
+        )
+        .unwrap();
+
+        assert!(noise[0].is_infinite());
+        assert!(*noise.last().unwrap() <= 0.1);
+        assert!(image.settings.aa_stages < 64);
+        assert_eq!(image.settings.aa_stages as usize, noise.len());
+    }
+
+    #[test]
+    fn test_adaptive_spends_fewer_samples() {
+        let scene = Scene::new(describe_scene2(), Camera::default());
+        // the noise estimate takes the samples to be independent, low discrepancy ones
+        // converge faster than it thinks.
+        let sc = ShaderConstants {
+            width: 64,
+            height: 32,
+            dof: DOF_NONE,
+            sampler: SAMPLER_INDEPENDENT,
+            ..settings()
+        };
+
+        let reference = render(
+            &scene,
+            &ShaderConstants {
+                aa_stages: 512,
+                seed: 1,
+                ..sc
+            },
+        )
+        .unwrap();
+        let error = |image: &Image| -> f32 {
+            image
+                .color
+                .iter()
+                .zip(reference.color.iter())
+                .map(|(a, b)| (a.xyz().min(Vec3::ONE) - b.xyz().min(Vec3::ONE)).length_squared())
+                .sum::<f32>()
+                / image.color.len() as f32
+        };
+
+        let uniform = render(
+            &scene,
+            &ShaderConstants {
+                aa_stages: 64,
+                ..sc
+            },
+        )
+        .unwrap();
+
+        let mut progress = None;
+        let adaptive = render_progressive(
+            &scene,
+            &ShaderConstants {
+                aa_stages: 256,
+                ..sc
+            },
+            &[Aov::SampleCount],
+            &ProgressiveSettings {
+                adaptive_threshold: Some(0.08),
+                min_samples: 8,
+                ..Default::default()
+            },
+            |r| progress = Some(r.progress()),
+        )
+        .unwrap();
+        let progress = progress.unwrap();
+
+        // at least as good as 64 samples everywhere, for well under that on average.
+        assert!(error(&adaptive) <= error(&uniform));
+        assert!(progress.mean_samples < 48.0);
+        assert_eq!(progress.active, 0);
+
+        let samples = &adaptive.aov(Aov::SampleCount).unwrap().channels[0];
+        assert!(samples.contains(&8.0));
+        assert!(samples.iter().any(|n| *n > 64.0));
+    }
+}
@@ -0,0 +1,184 @@
+use spirv_std::glam::Vec3;
+
+use crate::{
+    hittable::{Hitable, Interval},
+    material::Material,
+    ray::Ray,
+    Scene,
+};
+
+/// Auxiliary buffers that can be rendered alongside the beauty pass.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    // world space shading normal of the first hit.
+    Normal,
+    Albedo,
+    // distance along the camera's view axis.
+    Depth,
+    Position,
+    MaterialId,
+    // position of the object in the scene's object list.
+    ObjectId,
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Depth,
+        Aov::Position,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::SampleCount,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::SampleCount => "samples",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Aov::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    /// channel names, following the usual exr conventions.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Depth => &["Z"],
+            Aov::MaterialId | Aov::ObjectId | Aov::SampleCount => &["V"],
+        }
+    }
+}
+
+/// Aovs of one pixel, accumulated over its samples. Normal, albedo and position are averaged
+/// like the beauty, depth and the ids come from the first sample that hits since blending those
+/// gives values that belong to neither surface. Misses leave zeros, infinite depth and ids of -1.
+#[derive(Copy, Clone, Debug)]
+pub struct AovPixel {
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub position: Vec3,
+    pub depth: f32,
+    pub material_id: f32,
+    pub object_id: f32,
+    pub samples: u32,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        Self {
+            normal: Vec3::ZERO,
+            albedo: Vec3::ZERO,
+            position: Vec3::ZERO,
+            depth: f32::INFINITY,
+            material_id: -1.0,
+            object_id: -1.0,
+            samples: 0,
+        }
+    }
+}
+
+impl AovPixel {
+    /// adds the first hit of the camera ray `r`.
+    pub fn add(&mut self, scene: &Scene, r: &Ray) {
+        self.samples += 1;
+
+        let Some(h) = scene.world.hit(r, Interval::new(0.0, f32::INFINITY)) else {
+            return;
+        };
+
+        self.normal += h.normal;
+        self.albedo += h.material.albedo(&h);
+        self.position += h.position;
+
+        // still infinite until a sample hits something.
+        if self.depth.is_infinite() {
+            let (_, _, cw) = scene.camera.basis();
+            self.depth = (h.position - r.origin).dot(cw);
+            self.material_id = h.material.id() as f32;
+            self.object_id = h.object_id as f32;
+        }
+    }
+
+    /// turns the sums into averages once all samples are in.
+    pub fn finish(&mut self) {
+        if self.samples > 0 {
+            let n = self.samples as f32;
+            self.normal /= n;
+            self.albedo /= n;
+            self.position /= n;
+        }
+    }
+
+    /// values for the channels of `aov`, in the order of `Aov::channels`.
+    pub fn get(&self, aov: Aov) -> Vec3 {
+        match aov {
+            Aov::Normal => self.normal,
+            Aov::Albedo => self.albedo,
+            Aov::Depth => Vec3::splat(self.depth),
+            Aov::Position => self.position,
+            Aov::MaterialId => Vec3::splat(self.material_id),
+            Aov::ObjectId => Vec3::splat(self.object_id),
+            Aov::SampleCount => Vec3::splat(self.samples as f32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittable::{HittableE, Sphere},
+        material::{LambertianMaterial, MaterialE},
+    };
+    use spirv_std::glam::vec3;
+
+    #[test]
+    fn test_first_hit_aovs() {
+        let red = MaterialE::Lambertian(LambertianMaterial::new(vec3(1.0, 0.0, 0.0)));
+        let blue = MaterialE::Lambertian(LambertianMaterial::new(vec3(0.0, 0.0, 1.0)));
+
+        let world = HittableE::bvh(vec![
+            HittableE::Sphere(Sphere::new(vec3(-2.0, 0.0, -5.0), 1.0, red.clone())),
+            HittableE::Sphere(Sphere::new(vec3(2.0, 0.0, -5.0), 1.0, blue.clone())),
+            HittableE::Sphere(Sphere::new(vec3(0.0, 0.0, -5.0), 1.0, red.clone())),
+        ]);
+        let camera = Camera::new(Vec3::ZERO, vec3(0.0, 0.0, -1.0), Vec3::Y, 40.0);
+        let scene = Scene::new(world, camera);
+
+        let mut px = AovPixel::default();
+        px.add(&scene, &Ray::new(Vec3::ZERO, vec3(0.0, 0.0, -1.0)));
+        px.add(&scene, &Ray::new(Vec3::ZERO, vec3(0.0, 1.0, 0.0)));
+        px.finish();
+
+        assert_eq!(px.samples, 2);
+        assert_eq!(px.object_id, 2.0);
+        assert_eq!(px.material_id, red.id() as f32);
+        assert_ne!(red.id(), blue.id());
+        assert!((px.depth - 4.0).abs() < 1e-5);
+        // the miss halves the averaged values but not the first hit ones.
+        assert!(px.normal.distance(vec3(0.0, 0.0, 0.5)) < 1e-5);
+        assert!(px.albedo.distance(vec3(0.5, 0.0, 0.0)) < 1e-5);
+
+        // a miss first doesn't keep the pixel at background values.
+        let mut px = AovPixel::default();
+        px.add(&scene, &Ray::new(Vec3::ZERO, vec3(0.0, 1.0, 0.0)));
+        px.add(&scene, &Ray::new(Vec3::ZERO, vec3(0.0, 0.0, -1.0)));
+        px.finish();
+
+        assert_eq!(px.object_id, 2.0);
+        assert_eq!(px.material_id, red.id() as f32);
+        assert!((px.depth - 4.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,543 @@
+use spirv_std::glam::Vec3;
+
+use crate::{
+    hittable::{Hit, Hitable, HittableE, Interval},
+    ray::Ray,
+};
+
+// number of buckets used when evaluating the surface area heuristic.
+const SAH_BUCKETS: usize = 12;
+// relative cost of testing a primitive vs traversing a node.
+const SAH_TRAVERSAL_COST: f32 = 0.125;
+const MAX_LEAF_SIZE: usize = 4;
+// deep enough for any tree built from a few million primitives.
+const STACK_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(-f32::INFINITY),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, o: &Aabb) -> Self {
+        Self {
+            min: self.min.min(o.min),
+            max: self.max.max(o.max),
+        }
+    }
+
+    pub fn grow(&self, p: Vec3) -> Self {
+        Self {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    // widen the box by a small relative amount so that primitives whose intersection
+    // has some floating point error are never culled by their own bounds.
+    pub fn padded(&self) -> Self {
+        let pad = (self.max.abs().max(self.min.abs()) * 1e-5) + Vec3::splat(1e-5);
+        Self {
+            min: self.min - pad,
+            max: self.max + pad,
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// slab test, returns the entry distance along the ray if the box overlaps `t`.
+    pub fn hit(&self, r: &Ray, inv_dir: Vec3, t: &Interval) -> Option<f32> {
+        let t0 = (self.min - r.origin) * inv_dir;
+        let t1 = (self.max - r.origin) * inv_dir;
+
+        let t_near = t0.min(t1).max_element().max(t.min);
+        // a tiny bit of slack to stay conservative, see pbrt's robust ray-bounds intersection.
+        let t_far = t0.max(t1).min_element() * 1.000_000_4;
+        let t_far = t_far.min(t.max);
+
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    // for leaves the first primitive, otherwise the index of the left child.
+    // the right child is always stored directly after the left one.
+    pub first: u32,
+    // number of primitives, 0 for interior nodes.
+    pub count: u32,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Bounding volume hierarchy over a list of hittables, built with the surface area heuristic.
+/// Nodes are stored flat so the tree can be uploaded to the gpu as is.
+#[derive(Clone)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub primitives: Vec<HittableE>,
+    // position of each primitive in the list the bvh was built from, used to break ties
+    // the same way `HittableE::List` does.
+    pub order: Vec<u32>,
+}
+
+struct BuildPrim {
+    bounds: Aabb,
+    centroid: Vec3,
+    index: u32,
+}
+
+impl Bvh {
+    pub fn new(list: Vec<HittableE>) -> Self {
+        let bounds: Vec<Aabb> = list.iter().map(|h| h.bounding_box()).collect();
+        let (nodes, order) = build(&bounds);
+
+        let mut list: Vec<Option<HittableE>> = list.into_iter().map(Some).collect();
+        let primitives = order
+            .iter()
+            .map(|i| list[*i as usize].take().unwrap())
+            .collect();
+
+        Self {
+            nodes,
+            primitives,
+            order,
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+}
+
+/// Builds the node array for a set of primitive bounds. Returns the nodes and the
+/// original index of each primitive in leaf order.
+pub fn build(bounds: &[Aabb]) -> (Vec<BvhNode>, Vec<u32>) {
+    let mut prims: Vec<BuildPrim> = bounds
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            let bounds = b.padded();
+            BuildPrim {
+                bounds,
+                centroid: bounds.centroid(),
+                index: i as u32,
+            }
+        })
+        .collect();
+
+    let mut nodes = Vec::with_capacity((2 * prims.len()).max(1));
+    nodes.push(BvhNode {
+        bounds: Aabb::empty(),
+        first: 0,
+        count: 0,
+    });
+
+    if !prims.is_empty() {
+        subdivide(&mut nodes, &mut prims, 0, 0, 0);
+    }
+
+    (nodes, prims.iter().map(|p| p.index).collect())
+}
+
+fn subdivide(
+    nodes: &mut Vec<BvhNode>,
+    build: &mut [BuildPrim],
+    node: usize,
+    first: usize,
+    depth: usize,
+) {
+    let bounds = build.iter().fold(Aabb::empty(), |b, p| b.union(&p.bounds));
+    let centroid_bounds = build.iter().fold(Aabb::empty(), |b, p| b.grow(p.centroid));
+
+    let count = build.len();
+
+    nodes[node].bounds = bounds;
+    nodes[node].first = first as u32;
+    nodes[node].count = count as u32;
+
+    // every level can leave one node on the traversal stack.
+    if count == 1 || depth >= STACK_SIZE - 2 {
+        return;
+    }
+
+    let mid = match find_split(build, &bounds, &centroid_bounds) {
+        Some((axis, pos, cost)) if cost < count as f32 || count > MAX_LEAF_SIZE => {
+            partition(build, |p| p.centroid[axis] < pos)
+        }
+        _ => 0,
+    };
+
+    let mid = if (mid == 0 || mid == count) && count > MAX_LEAF_SIZE {
+        // coincident centroids, fall back to a median split on the list order.
+        build.sort_by_key(|p| p.index);
+        count / 2
+    } else {
+        mid
+    };
+
+    if mid == 0 || mid == count {
+        return;
+    }
+
+    let left = nodes.len();
+    nodes.push(BvhNode {
+        bounds: Aabb::empty(),
+        first: 0,
+        count: 0,
+    });
+    nodes.push(BvhNode {
+        bounds: Aabb::empty(),
+        first: 0,
+        count: 0,
+    });
+
+    nodes[node].first = left as u32;
+    nodes[node].count = 0;
+
+    let (l, r) = build.split_at_mut(mid);
+    subdivide(nodes, l, left, first, depth + 1);
+    subdivide(nodes, r, left + 1, first + mid, depth + 1);
+}
+
+// returns (axis, split position, cost) of the cheapest bucket boundary.
+fn find_split(
+    build: &[BuildPrim],
+    bounds: &Aabb,
+    centroid_bounds: &Aabb,
+) -> Option<(usize, f32, f32)> {
+    let parent_area = bounds.surface_area();
+    if parent_area <= 0.0 {
+        return None;
+    }
+
+    let mut best: Option<(usize, f32, f32)> = None;
+
+    for axis in 0..3 {
+        let lo = centroid_bounds.min[axis];
+        let hi = centroid_bounds.max[axis];
+        if hi <= lo {
+            continue;
+        }
+
+        let scale = SAH_BUCKETS as f32 / (hi - lo);
+        let mut counts = [0u32; SAH_BUCKETS];
+        let mut boxes = [Aabb::empty(); SAH_BUCKETS];
+
+        for p in build {
+            let b = (((p.centroid[axis] - lo) * scale) as usize).min(SAH_BUCKETS - 1);
+            counts[b] += 1;
+            boxes[b] = boxes[b].union(&p.bounds);
+        }
+
+        for split in 1..SAH_BUCKETS {
+            let (l, r) =
+                (0..SAH_BUCKETS).fold(((Aabb::empty(), 0), (Aabb::empty(), 0)), |(l, r), i| {
+                    if i < split {
+                        ((l.0.union(&boxes[i]), l.1 + counts[i]), r)
+                    } else {
+                        (l, (r.0.union(&boxes[i]), r.1 + counts[i]))
+                    }
+                });
+
+            if l.1 == 0 || r.1 == 0 {
+                continue;
+            }
+
+            let cost = SAH_TRAVERSAL_COST
+                + (l.0.surface_area() * l.1 as f32 + r.0.surface_area() * r.1 as f32) / parent_area;
+
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                let pos = lo + split as f32 / scale;
+                best = Some((axis, pos, cost));
+            }
+        }
+    }
+
+    best
+}
+
+// in place partition, returns the number of elements for which `pred` holds.
+fn partition<T>(s: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..s.len() {
+        if pred(&s[i]) {
+            s.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+// smallest float larger than `x`, used so that an equal distance hit can still replace
+// the current one when it came earlier in the original list.
+fn next_up(x: f32) -> f32 {
+    if !x.is_finite() {
+        x
+    } else if x == 0.0 {
+        f32::from_bits(1)
+    } else if x > 0.0 {
+        f32::from_bits(x.to_bits() + 1)
+    } else {
+        f32::from_bits(x.to_bits() - 1)
+    }
+}
+
+/// Walks the tree front to back, `hit_prim` is called with the leaf order index of every
+/// primitive whose bounds overlap the ray. On equal distances the primitive that came first
+/// in `order` wins, which keeps results identical to a linear list.
+pub fn traverse(
+    nodes: &[BvhNode],
+    order: &[u32],
+    r: &Ray,
+    t: &Interval,
+    mut hit_prim: impl FnMut(usize, Interval) -> Option<Hit>,
+) -> Option<Hit> {
+    if order.is_empty() {
+        return None;
+    }
+
+    let inv_dir = r.direction.recip();
+
+    let mut closest = t.max;
+    let mut closest_order = u32::MAX;
+    let mut hit: Option<Hit> = None;
+
+    let mut stack = [0u32; STACK_SIZE];
+    let mut sp = 1;
+
+    while sp > 0 {
+        sp -= 1;
+        let node = &nodes[stack[sp] as usize];
+
+        // inclusive upper bound so that ties can still be resolved by list order.
+        let range = Interval::new(t.min, next_up(closest));
+        if node.bounds.hit(r, inv_dir, &range).is_none() {
+            continue;
+        }
+
+        if node.is_leaf() {
+            for i in node.first..node.first + node.count {
+                let o = order[i as usize];
+                let max = if o < closest_order {
+                    next_up(closest)
+                } else {
+                    closest
+                };
+
+                if let Some(h) = hit_prim(i as usize, Interval::new(t.min, max)) {
+                    if h.t < closest || o < closest_order {
+                        closest = h.t;
+                        closest_order = o;
+                        hit = Some(h);
+                    }
+                }
+            }
+        } else {
+            let (l, r_) = (node.first, node.first + 1);
+            let dl = nodes[l as usize].bounds.hit(r, inv_dir, &range);
+            let dr = nodes[r_ as usize].bounds.hit(r, inv_dir, &range);
+
+            // push the far child first so the near one is visited next.
+            match (dl, dr) {
+                (Some(a), Some(b)) => {
+                    let (near, far) = if a <= b { (l, r_) } else { (r_, l) };
+                    stack[sp] = far;
+                    stack[sp + 1] = near;
+                    sp += 2;
+                }
+                (Some(_), None) => {
+                    stack[sp] = l;
+                    sp += 1;
+                }
+                (None, Some(_)) => {
+                    stack[sp] = r_;
+                    sp += 1;
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    hit
+}
+
+/// Walks the tree until `hit_prim` reports a hit for one of the primitives whose bounds
+/// overlap the ray, without looking for the closest one. For shadow rays.
+pub fn any_hit(
+    nodes: &[BvhNode],
+    order: &[u32],
+    r: &Ray,
+    t: &Interval,
+    mut hit_prim: impl FnMut(usize, Interval) -> bool,
+) -> bool {
+    if order.is_empty() {
+        return false;
+    }
+
+    let inv_dir = r.direction.recip();
+
+    let mut stack = [0u32; STACK_SIZE];
+    let mut sp = 1;
+
+    while sp > 0 {
+        sp -= 1;
+        let node = &nodes[stack[sp] as usize];
+
+        if node.bounds.hit(r, inv_dir, t).is_none() {
+            continue;
+        }
+
+        if node.is_leaf() {
+            for i in node.first..node.first + node.count {
+                if hit_prim(i as usize, *t) {
+                    return true;
+                }
+            }
+        } else {
+            stack[sp] = node.first;
+            stack[sp + 1] = node.first + 1;
+            sp += 2;
+        }
+    }
+
+    false
+}
+
+impl Hitable for Bvh {
+    fn hit(&self, r: &Ray, t: Interval) -> Option<Hit> {
+        traverse(&self.nodes, &self.order, r, &t, |i, t| {
+            let h = self.primitives[i].hit(r, t)?;
+            Some(Hit {
+                object_id: self.order[i],
+                ..h
+            })
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds()
+    }
+
+    fn occluded(&self, r: &Ray, t: Interval) -> bool {
+        any_hit(&self.nodes, &self.order, r, &t, |i, t| {
+            self.primitives[i].occluded(r, t)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Sphere,
+        material::{LambertianMaterial, MaterialE},
+        util::hash32,
+    };
+    use spirv_std::glam::{vec2, vec3};
+
+    fn random_spheres(n: u32) -> Vec<HittableE> {
+        (0..n)
+            .map(|i| {
+                let p = hash32(vec2(i as f32, 0.37)) * 20.0 - 10.0;
+                let r = hash32(vec2(0.91, i as f32)).x * 0.8 + 0.05;
+                let mat = MaterialE::Lambertian(LambertianMaterial::new(Vec3::splat(0.5)));
+                HittableE::Sphere(Sphere::new(p, r, mat))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let spheres = random_spheres(500);
+        let list = HittableE::List(spheres.clone());
+        let bvh = HittableE::Bvh(Bvh::new(spheres));
+
+        let mut hits = 0;
+        for i in 0..5000 {
+            let o = hash32(vec2(i as f32, 1.3)) * 30.0 - 15.0;
+            let d = hash32(vec2(2.1, i as f32)) * 2.0 - 1.0;
+            let r = Ray::new(o, d);
+
+            let a = list.hit(&r, Interval::new(0.0001, f32::INFINITY));
+            let b = bvh.hit(&r, Interval::new(0.0001, f32::INFINITY));
+
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert_eq!(a.t, b.t);
+                    assert_eq!(a.position, b.position);
+                    assert_eq!(a.normal, b.normal);
+                    assert_eq!(a.front_face, b.front_face);
+                }
+                (None, None) => {}
+                _ => panic!("bvh and list disagree for ray {}", i),
+            }
+
+            // any hit traversal stops early but has to agree on whether there is one.
+            let short = Interval::new(0.0001, 5.0);
+            let occluded = bvh.occluded(&r, short);
+            assert_eq!(occluded, list.hit(&r, short).is_some());
+            assert_eq!(occluded, list.occluded(&r, short));
+        }
+
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn test_bvh_ties_follow_list_order() {
+        let a = MaterialE::Lambertian(LambertianMaterial::new(vec3(1.0, 0.0, 0.0)));
+        let b = MaterialE::Lambertian(LambertianMaterial::new(vec3(0.0, 1.0, 0.0)));
+
+        // identical spheres, the list always reports the first one.
+        let spheres = vec![
+            HittableE::Sphere(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, a)),
+            HittableE::Sphere(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, b)),
+        ];
+
+        let bvh = Bvh::new(spheres);
+        let r = Ray::new(Vec3::ZERO, vec3(0.0, 0.0, -1.0));
+        let h = bvh.hit(&r, Interval::new(0.0, f32::INFINITY)).unwrap();
+
+        match h.material {
+            MaterialE::Lambertian(m) => assert_eq!(m.albedo, vec3(1.0, 0.0, 0.0).into()),
+            _ => panic!("unexpected material"),
+        }
+    }
+}
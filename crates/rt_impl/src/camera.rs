@@ -0,0 +1,175 @@
+use spirv_std::glam::{vec3, Vec2, Vec3};
+
+use crate::{
+    depth::DOF_THIN_LENS, gltf::GltfCamera, ray::Ray, sampler::Sampler, util, ShaderConstants,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    // vertical field of view in degrees
+    pub vfov: f32,
+    // width / height of the film, `None` follows the output image.
+    pub aspect: Option<f32>,
+    // diameter of the lens, 0 for a pinhole camera.
+    pub aperture: f32,
+    // distance from the camera to the plane that is in focus.
+    pub focus_distance: f32,
+}
+
+impl Camera {
+    pub fn new(position: Vec3, look_at: Vec3, up: Vec3, vfov: f32) -> Self {
+        Self {
+            position,
+            look_at,
+            up,
+            vfov,
+            aspect: None,
+            aperture: 0.0,
+            focus_distance: position.distance(look_at),
+        }
+    }
+
+    /// right, up and forward vectors of the camera.
+    pub fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let cw = (self.look_at - self.position).normalize();
+        let cu = cw.cross(self.up).normalize();
+        let cv = cu.cross(cw);
+        (cu, cv, cw)
+    }
+
+    /// primary ray through `uv`, where y spans [-1, 1] from the bottom to the top of the
+    /// image and x spans [-w / h, w / h]. With `DOF_THIN_LENS` the ray starts on a random
+    /// point of the lens and passes through the focus plane where the pinhole ray would.
+    /// The ray's cone is a pixel wide.
+    pub fn get_ray(&self, sc: &ShaderConstants, uv: Vec2, sampler: &mut Sampler) -> Ray {
+        let image_aspect = sc.width as f32 / sc.height as f32;
+        let aspect = self.aspect.unwrap_or(image_aspect);
+        let h = (self.vfov.to_radians() * 0.5).tan();
+
+        let (cu, cv, cw) = self.basis();
+        let x = uv.x * (aspect / image_aspect) * h;
+        let y = uv.y * h;
+
+        let rd = cu * x + cv * y + cw;
+        // angle a pixel covers in the middle of the image, y spans 2h over the height.
+        let spread = 2.0 * h / sc.height as f32;
+
+        if sc.dof != DOF_THIN_LENS || self.aperture <= 0.0 {
+            return Ray::new(self.position, rd.normalize()).with_cone(0.0, spread);
+        }
+
+        // rd has unit length along cw, so this lands on the focus plane.
+        let focus = self.position + rd * self.focus_distance;
+
+        let lens = util::disk_point(self.aperture * 0.5, sampler.next_vec2());
+        let origin = self.position + cu * lens.x + cv * lens.y;
+
+        // the lens blurs more than a pixel away from the focus plane, which the cone
+        // ignores.
+        Ray::new(origin, (focus - origin).normalize()).with_cone(0.0, spread)
+    }
+}
+
+impl Default for Camera {
+    // the shot the renderer has always used.
+    fn default() -> Self {
+        Self::new(
+            vec3(-2.0, 1.0, 1.0),
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            2.0 * 0.25f32.atan().to_degrees(),
+        )
+    }
+}
+
+impl From<GltfCamera> for Camera {
+    fn from(c: GltfCamera) -> Self {
+        Self {
+            aspect: c.aspect,
+            ..Camera::new(c.position, c.look_at, c.up, c.yfov.to_degrees())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set_camera;
+    use spirv_std::glam::{vec2, UVec2};
+
+    #[test]
+    fn test_default_matches_fixed_camera() {
+        let sc = ShaderConstants {
+            width: 1920,
+            height: 1080,
+            aa_stages: 1,
+            bounce_limit: 1,
+            focus_point: 1.0,
+            sky: 1,
+            dof: DOF_THIN_LENS,
+            seed: 0,
+            ..Default::default()
+        };
+
+        let cam = Camera::default();
+        let fixed = set_camera(vec3(-2.0, 1.0, 1.0), vec3(0.0, 0.0, -1.0), 0.0);
+
+        for uv in [
+            vec2(0.0, 0.0),
+            vec2(-1.7, 1.0),
+            vec2(1.7, -1.0),
+            vec2(0.3, 0.6),
+        ] {
+            let expected = fixed * vec3(uv.x, uv.y, 4.0).normalize();
+            let r = cam.get_ray(&sc, uv, &mut Sampler::new(&sc, UVec2::ZERO, 0));
+
+            assert!(r.direction.distance(expected) < 1e-5);
+            assert_eq!(r.origin, cam.position);
+        }
+    }
+
+    #[test]
+    fn test_thin_lens_focuses_on_plane() {
+        let sc = ShaderConstants {
+            width: 800,
+            height: 400,
+            aa_stages: 1,
+            bounce_limit: 1,
+            focus_point: 1.0,
+            sky: 1,
+            dof: DOF_THIN_LENS,
+            seed: 0,
+            ..Default::default()
+        };
+
+        let cam = Camera {
+            aperture: 0.5,
+            focus_distance: 5.0,
+            ..Camera::new(Vec3::ZERO, vec3(0.0, 0.0, -1.0), vec3(0.0, 1.0, 0.0), 40.0)
+        };
+
+        let uv = vec2(0.4, -0.2);
+        let pinhole = Camera {
+            aperture: 0.0,
+            ..cam
+        }
+        .get_ray(&sc, uv, &mut Sampler::new(&sc, UVec2::ZERO, 0));
+        let focus = pinhole.direction * (5.0 / pinhole.direction.dot(vec3(0.0, 0.0, -1.0)));
+
+        let mut spread = 0.0f32;
+        for i in 0..100 {
+            let r = cam.get_ray(&sc, uv, &mut Sampler::new(&sc, UVec2::ZERO, i));
+            spread = spread.max(r.origin.length());
+
+            // every ray through the lens meets the pinhole ray on the focus plane.
+            let t = (-5.0 - r.origin.z) / r.direction.z;
+            assert!((r.origin + r.direction * t).distance(focus) < 1e-4);
+            assert!(r.origin.length() <= 0.25 + 1e-6);
+        }
+
+        assert!(spread > 0.1);
+    }
+}
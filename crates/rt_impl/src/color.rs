@@ -1,6 +1,8 @@
 use spirv_std::glam;
 
-use glam::Vec3;
+use glam::{mat3, vec3, Mat3, Vec3};
+
+use crate::ShaderConstants;
 
 pub trait Saturate {
     fn saturate(self) -> Self;
@@ -15,3 +17,156 @@ impl Saturate for Vec3 {
         v
     }
 }
+
+// values for `ShaderConstants::working_space`, the primaries scene colors are given in.
+// rendering itself is the same in both, colors are converted to rec.709 for tone mapping.
+pub const WORKING_REC709: u32 = 0;
+pub const WORKING_ACESCG: u32 = 1;
+
+// values for `ShaderConstants::display`, how 8 bit output is encoded.
+pub const DISPLAY_SRGB: u32 = 0;
+pub const DISPLAY_REC709: u32 = 1;
+// display p3, p3 primaries with a d65 white and the srgb curve.
+pub const DISPLAY_P3: u32 = 2;
+// rec.709 primaries with a plain square root, what the renderer wrote before.
+pub const DISPLAY_GAMMA2: u32 = 3;
+
+/// CIE xy chromaticities of the red, green and blue primaries and the white point.
+pub type Chromaticities = [f32; 8];
+
+pub const REC709_CHROMATICITIES: Chromaticities =
+    [0.64, 0.33, 0.30, 0.60, 0.15, 0.06, 0.3127, 0.3290];
+pub const ACESCG_CHROMATICITIES: Chromaticities =
+    [0.713, 0.293, 0.165, 0.830, 0.128, 0.044, 0.32168, 0.33767];
+pub const P3_D65_CHROMATICITIES: Chromaticities =
+    [0.680, 0.320, 0.265, 0.690, 0.150, 0.060, 0.3127, 0.3290];
+
+// ACEScg to linear rec.709 with a bradford adaptation from d60 to d65, column major.
+fn acescg_to_rec709() -> Mat3 {
+    mat3(
+        vec3(1.705051, -0.130256, -0.024003),
+        vec3(-0.621792, 1.140805, -0.128969),
+        vec3(-0.083258, -0.010548, 1.152972),
+    )
+}
+
+fn rec709_to_p3() -> Mat3 {
+    mat3(
+        vec3(0.822462, 0.033194, 0.017083),
+        vec3(0.177538, 0.966806, 0.072397),
+        vec3(0.0, 0.0, 0.910520),
+    )
+}
+
+pub fn working_chromaticities(sc: &ShaderConstants) -> Chromaticities {
+    match sc.working_space {
+        WORKING_ACESCG => ACESCG_CHROMATICITIES,
+        _ => REC709_CHROMATICITIES,
+    }
+}
+
+/// linear working space color in rec.709 primaries, which tone mapping and `luminance`
+/// expect.
+pub fn to_rec709(sc: &ShaderConstants, c: Vec3) -> Vec3 {
+    match sc.working_space {
+        WORKING_ACESCG => acescg_to_rec709() * c,
+        _ => c,
+    }
+}
+
+/// linear rec.709 color in the primaries of the display.
+pub fn to_display_primaries(sc: &ShaderConstants, c: Vec3) -> Vec3 {
+    match sc.display {
+        DISPLAY_P3 => rec709_to_p3() * c,
+        _ => c,
+    }
+}
+
+/// the srgb transfer function, IEC 61966-2-1.
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x.max(0.0)
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// inverse of `srgb_oetf`, decodes srgb encoded texels to linear.
+pub fn srgb_eotf(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// the rec.709 camera transfer function, ITU-R BT.709.
+pub fn rec709_oetf(x: f32) -> f32 {
+    if x < 0.018 {
+        4.5 * x.max(0.0)
+    } else {
+        1.099 * x.powf(0.45) - 0.099
+    }
+}
+
+/// relative luminance of a linear rec.709 color.
+pub fn luminance(c: Vec3) -> f32 {
+    c.dot(vec3(0.2126, 0.7152, 0.0722))
+}
+
+pub fn srgb_encode(c: Vec3) -> Vec3 {
+    vec3(srgb_oetf(c.x), srgb_oetf(c.y), srgb_oetf(c.z))
+}
+
+/// Turns tone mapped linear rec.709 color into display encoded values in [0, 1].
+pub fn output_transform(sc: &ShaderConstants, c: Vec3) -> Vec3 {
+    let c = to_display_primaries(sc, c).saturate();
+
+    let oetf = match sc.display {
+        DISPLAY_REC709 => rec709_oetf,
+        DISPLAY_GAMMA2 => f32::sqrt,
+        _ => srgb_oetf,
+    };
+
+    vec3(oetf(c.x), oetf(c.y), oetf(c.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4613).abs() < 1e-3);
+        // the two pieces meet.
+        let t = 0.0031308f32;
+        assert!((12.92 * t - (1.055 * t.powf(1.0 / 2.4) - 0.055)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_white_survives_gamut_conversion() {
+        for working_space in [WORKING_REC709, WORKING_ACESCG] {
+            for display in [DISPLAY_SRGB, DISPLAY_REC709, DISPLAY_P3, DISPLAY_GAMMA2] {
+                let sc = ShaderConstants {
+                    working_space,
+                    display,
+                    ..Default::default()
+                };
+
+                let white = to_display_primaries(&sc, to_rec709(&sc, Vec3::ONE));
+                assert!(white.distance(Vec3::ONE) < 2e-3);
+                let encoded = output_transform(&sc, to_rec709(&sc, Vec3::ONE));
+                assert!(encoded.distance(Vec3::ONE) < 2e-3);
+            }
+        }
+
+        // rec.709 red is inside p3, so it has no negative components there.
+        let sc = ShaderConstants {
+            display: DISPLAY_P3,
+            ..Default::default()
+        };
+        assert!(to_display_primaries(&sc, vec3(1.0, 0.0, 0.0)).min_element() >= 0.0);
+    }
+}
@@ -0,0 +1,168 @@
+use spirv_std::glam::{ivec2, IVec2, UVec2, Vec3};
+
+use crate::{aov::AovPixel, ShaderConstants};
+
+// values for `ShaderConstants::denoise`.
+pub const DENOISE_NONE: u32 = 0;
+// edge avoiding a-trous wavelet filter guided by the normal, albedo and position aovs.
+pub const DENOISE_ATROUS: u32 = 1;
+
+/// number of `atrous_step` passes, the last one reaches 2^(n - 1) * 2 pixels out.
+pub const ATROUS_ITERATIONS: u32 = 5;
+
+// 1d B3 spline, the 5x5 kernel is its outer product.
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// how fast the weights fall off with the difference in each guide.
+const SIGMA_COLOR: f32 = 2.0;
+const SIGMA_NORMAL: f32 = 0.3;
+const SIGMA_ALBEDO: f32 = 0.1;
+// relative to the depth of the center pixel.
+const SIGMA_PLANE: f32 = 0.02;
+
+// albedo below this counts as a miss and isn't divided out.
+const MIN_ALBEDO: f32 = 1e-3;
+
+fn albedo_factor(guide: &AovPixel) -> Vec3 {
+    if guide.albedo.max_element() < MIN_ALBEDO {
+        Vec3::ONE
+    } else {
+        guide.albedo.max(Vec3::splat(MIN_ALBEDO))
+    }
+}
+
+/// Divides the albedo out of `color` so the filter only has to smooth the lighting,
+/// texture and material detail come back with `remodulate`.
+pub fn demodulate(color: Vec3, guide: &AovPixel) -> Vec3 {
+    color / albedo_factor(guide)
+}
+
+pub fn remodulate(color: Vec3, guide: &AovPixel) -> Vec3 {
+    color * albedo_factor(guide)
+}
+
+fn hit(guide: &AovPixel) -> bool {
+    guide.depth.is_finite()
+}
+
+// how much `q` is allowed to contribute to `p`, 1 for an identical surface.
+fn edge_weight(p: &AovPixel, q: &AovPixel, color_p: Vec3, color_q: Vec3, iteration: u32) -> f32 {
+    if hit(p) != hit(q) {
+        return 0.0;
+    }
+
+    // the color difference gets stricter each pass as the noise goes down.
+    let sigma_color = SIGMA_COLOR / (1u32 << iteration) as f32;
+    let dc = (color_p - color_q).length_squared() / (sigma_color * sigma_color);
+    if !hit(p) {
+        return (-dc).exp();
+    }
+
+    let dn = (p.normal - q.normal).length_squared() / (SIGMA_NORMAL * SIGMA_NORMAL);
+    let da = (p.albedo - q.albedo).length_squared() / (SIGMA_ALBEDO * SIGMA_ALBEDO);
+    // distance of `q` from the tangent plane at `p`, keeps neighbours on other surfaces
+    // out while letting a plane seen at a grazing angle blur along itself.
+    let sigma_plane = SIGMA_PLANE * p.depth.max(1e-3);
+    let plane = p.normal.dot(q.position - p.position) / sigma_plane;
+
+    (-(dc + dn + da + plane * plane)).exp()
+}
+
+/// One pass of the a-trous filter for the pixel at `idx`, `input` is demodulated radiance
+/// from the previous pass and `guide` the aovs of the first hits. Pass `iteration` samples
+/// its neighbours 2^iteration pixels apart.
+pub fn atrous_step(
+    sc: &ShaderConstants,
+    idx: UVec2,
+    iteration: u32,
+    input: &[Vec3],
+    guide: &[AovPixel],
+) -> Vec3 {
+    let size = ivec2(sc.width as i32, sc.height as i32);
+    let id = |p: IVec2| (p.y * size.x + p.x) as usize;
+
+    let center = idx.as_ivec2();
+    let color_p = input[id(center)];
+    let guide_p = &guide[id(center)];
+    let step = 1 << iteration;
+
+    let mut sum = Vec3::ZERO;
+    let mut total = 0.0;
+    for (j, ky) in KERNEL.iter().enumerate() {
+        for (i, kx) in KERNEL.iter().enumerate() {
+            let q = center + ivec2(i as i32 - 2, j as i32 - 2) * step;
+            if q.x < 0 || q.y < 0 || q.x >= size.x || q.y >= size.y {
+                continue;
+            }
+
+            let color_q = input[id(q)];
+            let w = kx * ky * edge_weight(guide_p, &guide[id(q)], color_p, color_q, iteration);
+            sum += color_q * w;
+            total += w;
+        }
+    }
+
+    // the center always has weight, so total can't be zero.
+    sum / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spirv_std::glam::{uvec2, vec3};
+
+    fn run(sc: &ShaderConstants, color: &[Vec3], guide: &[AovPixel]) -> Vec<Vec3> {
+        let mut buf = color.to_vec();
+        for iteration in 0..ATROUS_ITERATIONS {
+            buf = (0..sc.height)
+                .flat_map(|y| (0..sc.width).map(move |x| uvec2(x, y)))
+                .map(|idx| atrous_step(sc, idx, iteration, &buf, guide))
+                .collect();
+        }
+        buf
+    }
+
+    #[test]
+    fn test_keeps_edges_and_flat_areas() {
+        let sc = ShaderConstants {
+            width: 16,
+            height: 8,
+            ..Default::default()
+        };
+
+        // a wall facing the camera on the left, a floor on the right.
+        let guide: Vec<AovPixel> = (0..sc.width * sc.height)
+            .map(|i| {
+                let x = (i % sc.width) as f32;
+                let y = (i / sc.width) as f32;
+                let left = x < 8.0;
+                AovPixel {
+                    normal: if left { Vec3::Z } else { Vec3::Y },
+                    albedo: Vec3::splat(0.5),
+                    position: if left {
+                        vec3(x, y, -5.0)
+                    } else {
+                        vec3(x, -1.0, -y)
+                    },
+                    depth: 5.0,
+                    material_id: 0.0,
+                    object_id: 0.0,
+                    samples: 1,
+                }
+            })
+            .collect();
+        let color: Vec<Vec3> = (0..sc.width * sc.height)
+            .map(|i| {
+                if i % sc.width < 8 {
+                    Vec3::splat(0.2)
+                } else {
+                    Vec3::splat(0.8)
+                }
+            })
+            .collect();
+
+        for (a, b) in run(&sc, &color, &guide).iter().zip(color.iter()) {
+            assert!(a.distance(*b) < 1e-4);
+        }
+    }
+}
@@ -1,12 +1,19 @@
-use spirv_std::glam::{uvec2, vec2, vec3, vec4, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
+use spirv_std::glam::{uvec2, vec2, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
 
-use crate::{hittable::HittableE, util::linear_to_gamma, ShaderConstants};
+use crate::ShaderConstants;
 
 pub const MAX_BLUR_SIZE: f32 = 20.0;
 pub const GOLDEN_ANGLE: f32 = 2.39996322972865332;
 pub const UFAR: f32 = 10.0;
 pub const RAD_SCALE: f32 = 0.5;
 
+// values for `ShaderConstants::dof`.
+pub const DOF_NONE: u32 = 0;
+// screen space gather in `render_post_pass`.
+pub const DOF_POST: u32 = 1;
+// rays are traced through the camera's lens, see `Camera::aperture`.
+pub const DOF_THIN_LENS: u32 = 2;
+
 pub fn blur_size(depth: f32, focus_point: f32, focus_scale: f32) -> f32 {
     let coc = ((1.0 / focus_point - 1.0 / depth) * focus_scale).clamp(-1.0, 1.0);
     coc.abs() * MAX_BLUR_SIZE
@@ -29,7 +36,7 @@ pub fn depth_of_field(
     uv: Vec2,
     focus_point: f32,
     focus_scale: f32,
-    tex: &Vec<Vec4>,
+    tex: &[Vec4],
 ) -> Vec3 {
     let i = tex[uv_to_id(sc, uv)];
 
@@ -75,27 +82,18 @@ fn mix(a: Vec3, b: Vec3, x: f32) -> Vec3 {
     (a * (1.0 - x)) + (b * x)
 }
 
-pub fn render_depth_pass(
-    sc: &ShaderConstants,
-    _world: &HittableE,
-    idx: UVec2,
-    pass_one: &Vec<Vec4>,
-) -> Vec4 {
+/// linear color of the pixel at `idx` after the screen space depth of field, if enabled.
+pub fn render_post_pass(sc: &ShaderConstants, idx: UVec2, pass_one: &[Vec4]) -> Vec3 {
     let uv = idx.as_vec2() / uvec2(sc.width, sc.height).as_vec2();
 
-    // float focusPoint = 78.0;
     // float focusScale = (0.5 + sin(iTime) * 0.5) * 50.0;
-    let focus_point = 30.;
     let focus_scale = 1.0 * 50.0;
 
-    let mut color = depth_of_field(sc, uv, focus_point, focus_scale, pass_one);
-    // tone mapping
-    // color = vec3(1.7, 1.8, 1.9) * color / (1.0 + color);
-
-    // color = color.powf(1.0 / 1.8);
-    color = linear_to_gamma(color);
-
-    vec4(color.x, color.y, color.z, 1.0)
+    if sc.dof == DOF_POST {
+        depth_of_field(sc, uv, sc.focus_point, focus_scale, pass_one)
+    } else {
+        pass_one[uv_to_id(sc, uv)].xyz()
+    }
 }
 
 #[cfg(test)]
@@ -115,6 +113,10 @@ mod tests {
             aa_stages: 150,
             bounce_limit: 100,
             focus_point: 1.0,
+            sky: 1,
+            dof: DOF_POST,
+            seed: 0,
+            ..Default::default()
         };
 
         let correct: Vec<(u32, u32)> = (0..w)
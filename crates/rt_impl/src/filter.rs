@@ -0,0 +1,158 @@
+use std::f32::consts::PI;
+
+use spirv_std::glam::Vec2;
+
+use crate::ShaderConstants;
+
+// values for `ShaderConstants::filter`.
+pub const FILTER_BOX: u32 = 0;
+pub const FILTER_TENT: u32 = 1;
+pub const FILTER_GAUSSIAN: u32 = 2;
+// mitchell-netravali with B = C = 1/3.
+pub const FILTER_MITCHELL: u32 = 3;
+// sinc windowed by a sinc twice as wide, a = 2.
+pub const FILTER_LANCZOS: u32 = 4;
+pub const FILTER_BLACKMAN_HARRIS: u32 = 5;
+
+// standard deviation of `FILTER_GAUSSIAN` in pixels.
+const GAUSSIAN_SIGMA: f32 = 0.5;
+
+/// How far, in pixels, a sample counts towards the pixels around it. The box only
+/// covers the pixel the sample is in.
+pub fn filter_radius(sc: &ShaderConstants) -> f32 {
+    match sc.filter {
+        FILTER_TENT => 1.0,
+        FILTER_GAUSSIAN | FILTER_BLACKMAN_HARRIS => 1.5,
+        FILTER_MITCHELL | FILTER_LANCZOS => 2.0,
+        _ => 0.5,
+    }
+}
+
+/// How many pixels either side of the one a sample is in it has to be splatted into.
+/// Samples are at most half a pixel from their pixel's center.
+pub fn filter_reach(sc: &ShaderConstants) -> i32 {
+    (filter_radius(sc) + 0.5).ceil() as i32 - 1
+}
+
+/// Unnormalized weight of a sample `offset` pixels from the center of a pixel. The
+/// filters are separable and Mitchell and Lanczos go negative around their first zero.
+pub fn filter_weight(sc: &ShaderConstants, offset: Vec2) -> f32 {
+    let radius = filter_radius(sc);
+    if offset.x.abs() > radius || offset.y.abs() > radius {
+        return 0.0;
+    }
+
+    filter_1d(sc.filter, offset.x, radius) * filter_1d(sc.filter, offset.y, radius)
+}
+
+fn filter_1d(filter: u32, x: f32, radius: f32) -> f32 {
+    let x = x.abs();
+    match filter {
+        FILTER_TENT => 1.0 - x / radius,
+        FILTER_GAUSSIAN => gaussian(x) - gaussian(radius),
+        FILTER_MITCHELL => mitchell(x),
+        FILTER_LANCZOS => sinc(x) * sinc(x / radius),
+        FILTER_BLACKMAN_HARRIS => blackman_harris(0.5 + 0.5 * x / radius),
+        _ => 1.0,
+    }
+}
+
+// shifted down by its value at the radius in `filter_1d` so it reaches 0 there.
+fn gaussian(x: f32) -> f32 {
+    (-x * x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp()
+}
+
+// Mitchell and Netravali, "Reconstruction Filters in Computer Graphics" (1988).
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+
+    let (x2, x3) = (x * x, x * x * x);
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x3 + (-18.0 + 12.0 * B + 6.0 * C) * x2 + (6.0 - 2.0 * B))
+            / 6.0
+    } else {
+        ((-B - 6.0 * C) * x3
+            + (6.0 * B + 30.0 * C) * x2
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C))
+            / 6.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    let px = PI * x;
+    px.sin() / px
+}
+
+// 4 term window over t in [0, 1], 1 in the middle.
+fn blackman_harris(t: f32) -> f32 {
+    let a = 2.0 * PI * t;
+    0.35875 - 0.48829 * a.cos() + 0.14128 * (2.0 * a).cos() - 0.01168 * (3.0 * a).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spirv_std::glam::vec2;
+
+    const FILTERS: [u32; 6] = [
+        FILTER_BOX,
+        FILTER_TENT,
+        FILTER_GAUSSIAN,
+        FILTER_MITCHELL,
+        FILTER_LANCZOS,
+        FILTER_BLACKMAN_HARRIS,
+    ];
+
+    #[test]
+    fn test_weights() {
+        for filter in FILTERS {
+            let sc = ShaderConstants {
+                filter,
+                ..Default::default()
+            };
+            let radius = filter_radius(&sc);
+            let center = filter_weight(&sc, Vec2::ZERO);
+
+            assert!(center > 0.0);
+            assert_eq!(filter_weight(&sc, vec2(radius + 0.01, 0.0)), 0.0);
+            assert_eq!(filter_weight(&sc, vec2(0.1, radius + 0.1)), 0.0);
+
+            // symmetric, separable and never above the center.
+            for i in 0..50 {
+                let x = radius * i as f32 / 50.0;
+                let w = filter_weight(&sc, vec2(x, 0.3));
+                assert_eq!(w, filter_weight(&sc, vec2(-x, -0.3)));
+                assert_eq!(w, filter_weight(&sc, vec2(0.3, x)));
+                assert!(w <= center);
+            }
+
+            // the farthest pixel a sample is splatted into gets some of it, the next doesn't.
+            let reach = filter_reach(&sc) as f32;
+            if reach > 0.0 {
+                assert!(filter_weight(&sc, vec2(reach - 0.45, 0.0)) != 0.0);
+            }
+            assert_eq!(filter_weight(&sc, vec2(reach + 0.51, 0.0)), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_mitchell_and_lanczos_are_continuous() {
+        for filter in [FILTER_MITCHELL, FILTER_LANCZOS] {
+            let sc = ShaderConstants {
+                filter,
+                ..Default::default()
+            };
+            let w = |x: f32| filter_weight(&sc, vec2(x, 0.0));
+
+            assert!((w(0.9999) - w(1.0001)).abs() < 1e-3);
+            assert!(w(1.9999).abs() < 1e-3);
+            // negative lobe past the first zero.
+            assert!(w(1.3) < 0.0);
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use std::{collections::HashMap, fmt, path::Path, sync::Arc};
+
+use gltf::{
+    camera::Projection,
+    image::Format,
+    mesh::Mode,
+    texture::{MagFilter, WrappingMode},
+    Document, Node,
+};
+use spirv_std::glam::{uvec2, uvec3, vec2, vec3, Mat4, UVec3, Vec2, Vec3, Vec4Swizzles};
+
+use crate::{
+    color::srgb_eotf,
+    hittable::HittableE,
+    material::{
+        DialetricMaterial, DiffuseLightMaterial, LambertianMaterial, MaterialE, MetalMaterial,
+    },
+    mesh::{Mesh, MeshError},
+    mipmap::{MipLevel, MipMap, MIP_EWA, MIP_NEAREST, WRAP_CLAMP, WRAP_MIRROR, WRAP_REPEAT},
+    texture::{ImageTexture, TextureE},
+};
+
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(gltf::Error),
+    NoScene,
+    MissingPositions { mesh: String },
+    Mesh { mesh: String, source: MeshError },
+    // the node transform collapses the mesh, so there's no inverse for its normals.
+    SingularTransform { mesh: String },
+    // pixel data that doesn't match the image size and format.
+    BadImage { image: usize },
+    // a texture reads from a TEXCOORD_n the primitive doesn't have.
+    MissingUvs { mesh: String, set: u32 },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Gltf(e) => write!(f, "{}", e),
+            GltfError::NoScene => write!(f, "file contains no scene"),
+            GltfError::MissingPositions { mesh } => {
+                write!(f, "mesh '{}' has a primitive without positions", mesh)
+            }
+            GltfError::Mesh { mesh, source } => write!(f, "mesh '{}': {}", mesh, source),
+            GltfError::SingularTransform { mesh } => {
+                write!(
+                    f,
+                    "mesh '{}' is placed with a transform that can't be inverted",
+                    mesh
+                )
+            }
+            GltfError::BadImage { image } => write!(f, "image {} has no usable pixels", image),
+            GltfError::MissingUvs { mesh, set } => {
+                write!(f, "mesh '{}' is textured but has no TEXCOORD_{}", mesh, set)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Gltf(e) => Some(e),
+            GltfError::Mesh { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for GltfError {
+    fn from(e: gltf::Error) -> Self {
+        GltfError::Gltf(e)
+    }
+}
+
+/// Perspective camera found in the node hierarchy, in world space.
+#[derive(Copy, Clone, Debug)]
+pub struct GltfCamera {
+    pub position: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    // vertical field of view in radians
+    pub yfov: f32,
+    pub aspect: Option<f32>,
+}
+
+pub struct GltfScene {
+    pub world: HittableE,
+    pub cameras: Vec<GltfCamera>,
+}
+
+/// Loads a `.gltf` or `.glb` file. Buffers may be external files next to it, embedded base64
+/// data uris or the binary chunk of a glb, nothing is fetched over the network.
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfScene, GltfError> {
+    let (doc, buffers, images) = gltf::import(path)?;
+    let mut loader = Loader {
+        buffers: &buffers,
+        images: &images,
+        loaded: HashMap::new(),
+        meshes: vec![],
+        cameras: vec![],
+    };
+    loader.scene(&doc)?;
+
+    Ok(GltfScene {
+        world: HittableE::bvh(loader.meshes),
+        cameras: loader.cameras,
+    })
+}
+
+struct Loader<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    // images already converted, by image and base color factor.
+    loaded: HashMap<(usize, [u32; 3]), Arc<MipMap>>,
+    meshes: Vec<HittableE>,
+    cameras: Vec<GltfCamera>,
+}
+
+impl Loader<'_> {
+    fn scene(&mut self, doc: &Document) -> Result<(), GltfError> {
+        let scene = doc
+            .default_scene()
+            .or_else(|| doc.scenes().next())
+            .ok_or(GltfError::NoScene)?;
+
+        for node in scene.nodes() {
+            self.visit(&node, Mat4::IDENTITY)?;
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, node: &Node, parent: Mat4) -> Result<(), GltfError> {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if let Some(m) = self.primitive(&mesh, &primitive, transform)? {
+                    self.meshes.push(HittableE::Mesh(m));
+                }
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            // only perspective cameras map onto ours, orthographic ones are skipped.
+            if let Projection::Perspective(p) = camera.projection() {
+                // gltf cameras look down -z with +y up in their local space.
+                let position = transform.w_axis.xyz();
+                let forward = transform.transform_vector3(Vec3::NEG_Z).normalize();
+                let up = transform.transform_vector3(Vec3::Y).normalize();
+
+                self.cameras.push(GltfCamera {
+                    position,
+                    look_at: position + forward,
+                    up,
+                    yfov: p.yfov(),
+                    aspect: p.aspect_ratio(),
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.visit(&child, transform)?;
+        }
+
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        mesh: &gltf::Mesh,
+        primitive: &gltf::Primitive,
+        transform: Mat4,
+    ) -> Result<Option<Mesh>, GltfError> {
+        // points and lines have no surface to hit.
+        if !matches!(
+            primitive.mode(),
+            Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan
+        ) {
+            return Ok(None);
+        }
+
+        let buffers = self.buffers;
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+        let name = || mesh.name().unwrap_or("unnamed").to_string();
+
+        let positions: Vec<Vec3> = reader
+            .read_positions()
+            .ok_or_else(|| GltfError::MissingPositions { mesh: name() })?
+            .map(|p| transform.transform_point3(Vec3::from(p)))
+            .collect();
+
+        // normals transform with the inverse transpose to survive non uniform scaling.
+        let det = transform.determinant();
+        if det == 0.0 || !det.is_finite() {
+            return Err(GltfError::SingularTransform { mesh: name() });
+        }
+        let normal_matrix = transform.inverse().transpose();
+        let normals = reader
+            .read_normals()
+            .map(|n| {
+                n.map(|n| normal_matrix.transform_vector3(Vec3::from(n)).normalize())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let (material, uv_set) = self.material(&primitive.material())?;
+
+        // gltf puts v = 0 at the top of the image, ours is at the bottom. untextured
+        // materials don't need uvs, but reading them keeps the derivatives of the mesh.
+        let uvs = match reader.read_tex_coords(uv_set.unwrap_or(0)) {
+            Some(t) => t.into_f32().map(|t| vec2(t[0], 1.0 - t[1])).collect(),
+            None => match uv_set {
+                Some(set) => return Err(GltfError::MissingUvs { mesh: name(), set }),
+                None => vec![],
+            },
+        };
+
+        let flat: Vec<u32> = match reader.read_indices() {
+            Some(i) => i.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let indices: Vec<UVec3> = match primitive.mode() {
+            Mode::TriangleStrip => (2..flat.len())
+                .map(|i| {
+                    // every other triangle is flipped to keep the winding consistent.
+                    if i % 2 == 0 {
+                        uvec3(flat[i - 2], flat[i - 1], flat[i])
+                    } else {
+                        uvec3(flat[i - 1], flat[i - 2], flat[i])
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => (2..flat.len())
+                .map(|i| uvec3(flat[0], flat[i - 1], flat[i]))
+                .collect(),
+            _ => flat
+                .chunks_exact(3)
+                .map(|c| uvec3(c[0], c[1], c[2]))
+                .collect(),
+        };
+
+        let m = Mesh::new(positions, normals, uvs, indices, material).map_err(|source| {
+            GltfError::Mesh {
+                mesh: name(),
+                source,
+            }
+        })?;
+
+        Ok(Some(m))
+    }
+
+    // pbr metal-roughness mapped onto the closest of our materials, along with the uv set
+    // its base color texture reads from if it has one.
+    fn material(&mut self, m: &gltf::Material) -> Result<(MaterialE, Option<u32>), GltfError> {
+        let pbr = m.pbr_metallic_roughness();
+        let factor = Vec3::from_slice(&pbr.base_color_factor()[..3]);
+
+        let (base, uv_set) = match pbr.base_color_texture() {
+            Some(info) => (
+                self.texture(&info.texture(), factor)?,
+                Some(info.tex_coord()),
+            ),
+            None => (factor.into(), None),
+        };
+
+        let emissive = Vec3::from(m.emissive_factor()) * m.emissive_strength().unwrap_or(1.0);
+        if emissive.max_element() > 0.0 {
+            return Ok((
+                MaterialE::DiffuseLight(DiffuseLightMaterial::new(emissive)),
+                None,
+            ));
+        }
+
+        let transmission = m.transmission().map_or(0.0, |t| t.transmission_factor());
+        let material = if transmission > 0.5 {
+            MaterialE::Dialetric(DialetricMaterial::new(base, m.ior().unwrap_or(1.5)))
+        } else if pbr.metallic_factor() >= 0.5 {
+            MaterialE::Metal(MetalMaterial::new(base, pbr.roughness_factor()))
+        } else {
+            MaterialE::Lambertian(LambertianMaterial::new(base))
+        };
+
+        Ok((material, uv_set))
+    }
+
+    fn texture(&mut self, texture: &gltf::Texture, factor: Vec3) -> Result<TextureE, GltfError> {
+        let image = texture.source().index();
+        let key = (image, factor.to_array().map(f32::to_bits));
+
+        let mip = match self.loaded.get(&key) {
+            Some(mip) => mip.clone(),
+            None => {
+                let level = image_level(&self.images[image], factor)
+                    .ok_or(GltfError::BadImage { image })?;
+                let mip = Arc::new(MipMap::new(level));
+                self.loaded.insert(key, mip.clone());
+                mip
+            }
+        };
+
+        let sampler = texture.sampler();
+        let wrap = |mode| match mode {
+            WrappingMode::ClampToEdge => WRAP_CLAMP,
+            WrappingMode::MirroredRepeat => WRAP_MIRROR,
+            WrappingMode::Repeat => WRAP_REPEAT,
+        };
+        // nearest magnification asks for hard texel edges, anything else gets the best filter.
+        let filter = match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => MIP_NEAREST,
+            _ => MIP_EWA,
+        };
+
+        Ok(TextureE::Image(ImageTexture::new(
+            mip,
+            Vec2::ONE,
+            uvec2(wrap(sampler.wrap_s()), wrap(sampler.wrap_t())),
+            filter,
+        )))
+    }
+}
+
+// decoded image scaled by the base color factor. 8 and 16 bit images are srgb encoded, float
+// ones are already linear. alpha is dropped.
+fn image_level(data: &gltf::image::Data, factor: Vec3) -> Option<MipLevel> {
+    let (channels, bytes) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    let texels = (data.width as usize).checked_mul(data.height as usize)?;
+    if texels == 0 || data.pixels.len() != texels * channels * bytes {
+        return None;
+    }
+
+    // the importer hands over 16 bit and float channels in native byte order.
+    let value = |b: &[u8]| match bytes {
+        1 => srgb_eotf(b[0] as f32 / 255.0),
+        2 => srgb_eotf(u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.0),
+        _ => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+    };
+
+    let texels = data
+        .pixels
+        .chunks_exact(channels * bytes)
+        .map(|p| {
+            let c = |i: usize| value(&p[i * bytes..]);
+            let rgb = match channels {
+                1 | 2 => Vec3::splat(c(0)),
+                _ => vec3(c(0), c(1), c(2)),
+            };
+            rgb * factor
+        })
+        .collect();
+
+    Some(MipLevel::new(data.width, data.height, texels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Hitable, Interval},
+        material::Material,
+        ray::Ray,
+        util::TempDir,
+    };
+    use spirv_std::glam::vec3;
+
+    // one triangle in an embedded buffer, translated by +1 on z, and a camera at z = 3.
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 1] }],
+        "nodes": [
+            { "mesh": 0, "translation": [0.0, 0.0, 1.0] },
+            { "camera": 0, "translation": [0.0, 0.0, 3.0] }
+        ],
+        "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.8, "znear": 0.1 } }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.0, 0.0, 1.0], "metallicFactor": 1.0, "roughnessFactor": 0.25 } }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+        "buffers": [{ "byteLength": 36, "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAA" }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [-1.0, -1.0, 0.0], "max": [1.0, 1.0, 0.0] }]
+    }"#;
+
+    #[test]
+    fn test_embedded_triangle_and_camera() {
+        let dir = TempDir::new("gltf_triangle");
+        let scene = load_str(&dir, "triangle.gltf", TRIANGLE).unwrap();
+
+        assert_eq!(scene.cameras.len(), 1);
+        let cam = scene.cameras[0];
+        assert_eq!(cam.position, vec3(0.0, 0.0, 3.0));
+        assert_eq!(cam.look_at, vec3(0.0, 0.0, 2.0));
+        assert_eq!(cam.yfov, 0.8);
+
+        let r = Ray::new(cam.position, cam.look_at - cam.position);
+        let h = scene
+            .world
+            .hit(&r, Interval::new(0.0, f32::INFINITY))
+            .unwrap();
+
+        assert_eq!(h.t, 2.0);
+        assert!(matches!(h.material, MaterialE::Metal(m) if m.fuzz == TextureE::from(0.25)));
+    }
+
+    fn load_str(dir: &TempDir, name: &str, src: &str) -> Result<GltfScene, GltfError> {
+        let path = dir.join(name);
+        std::fs::write(&path, src).unwrap();
+        load_gltf(&path)
+    }
+
+    #[test]
+    fn test_glb_binary_chunk() {
+        // the triangle's positions, stored in the glb's own buffer instead of a data uri.
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "buffers": [{ "byteLength": 36 }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [-1.0, -1.0, 0.0], "max": [1.0, 1.0, 0.0] }]
+        }"#;
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let bin: Vec<u8> = [-1.0f32, -1.0, 0.0, 1.0, -1.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+
+        // 12 byte header, then each chunk with its length and type.
+        let mut glb = vec![];
+        glb.extend(b"glTF");
+        glb.extend(2u32.to_le_bytes());
+        glb.extend((12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(&json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(&bin);
+
+        let dir = TempDir::new("gltf_glb");
+        let path = dir.join("triangle.glb");
+        std::fs::write(&path, glb).unwrap();
+        let scene = load_gltf(&path).unwrap();
+
+        let r = Ray::new(vec3(0.0, 0.0, 1.0), Vec3::NEG_Z);
+        let h = scene
+            .world
+            .hit(&r, Interval::new(0.0, f32::INFINITY))
+            .unwrap();
+        assert_eq!(h.t, 1.0);
+    }
+
+    #[test]
+    fn test_malformed_meshes_are_errors() {
+        // the third index points past the three positions.
+        let bad_index = TRIANGLE
+            .replace(r#""POSITION": 0 }"#, r#""POSITION": 0 }, "indices": 1"#)
+            .replace(
+                r#""byteLength": 36, "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAA" }"#,
+                r#""byteLength": 44, "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAAAAABAAUAAAA=" }"#,
+            )
+            .replace(
+                r#""bufferViews": [{ "buffer": 0, "byteLength": 36 }]"#,
+                r#""bufferViews": [{ "buffer": 0, "byteLength": 36 }, { "buffer": 0, "byteOffset": 36, "byteLength": 6 }]"#,
+            )
+            .replace(
+                r#""max": [1.0, 1.0, 0.0] }]"#,
+                r#""max": [1.0, 1.0, 0.0] }, { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }]"#,
+            )
+            .replace(r#""meshes": [{ "primitives""#, r#""meshes": [{ "name": "sign", "primitives""#);
+
+        let dir = TempDir::new("gltf_malformed");
+        match load_str(&dir, "bad_index.gltf", &bad_index) {
+            Err(GltfError::Mesh { mesh, source }) => {
+                assert_eq!(mesh, "sign");
+                assert!(matches!(
+                    source,
+                    MeshError::IndexOutOfRange { index: 5, .. }
+                ));
+            }
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(_) => panic!("expected an error"),
+        }
+
+        let flat = TRIANGLE.replace(
+            r#""translation": [0.0, 0.0, 1.0] }"#,
+            r#""scale": [1.0, 0.0, 1.0] }"#,
+        );
+        assert!(matches!(
+            load_str(&dir, "flat.gltf", &flat),
+            Err(GltfError::SingularTransform { .. })
+        ));
+    }
+
+    // the triangle with its uvs in a second set, textured by a red and blue 2x1 png.
+    const TEXTURED: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [0.5, 1.0, 1.0, 1.0], "baseColorTexture": { "index": 0, "texCoord": 1 }, "metallicFactor": 0.0 } }],
+        "textures": [{ "source": 0, "sampler": 0 }],
+        "samplers": [{ "magFilter": 9728, "wrapS": 10497, "wrapT": 33071 }],
+        "images": [{ "uri": "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAIAAAB7QOjdAAAADUlEQVR4nGP4zwAE/wEHAAH/4iOeWQAAAABJRU5ErkJggg==" }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_1": 1 }, "material": 0 }] }],
+        "buffers": [{ "byteLength": 60, "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAAAAAAAAAAgD8AAIA/AACAPwAAAD8AAAAA" }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }, { "buffer": 0, "byteOffset": 36, "byteLength": 24 }],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [-1.0, -1.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }
+        ]
+    }"#;
+
+    #[test]
+    fn test_base_color_texture() {
+        let dir = TempDir::new("gltf_textured");
+        let path = dir.join("textured.gltf");
+        std::fs::write(&path, TEXTURED).unwrap();
+
+        let gltf = load_gltf(&path).unwrap();
+        let hit = |x: f32, y: f32| {
+            let r = Ray::new(vec3(x, y, 1.0), Vec3::NEG_Z);
+            gltf.world
+                .hit(&r, Interval::new(0.0, f32::INFINITY))
+                .unwrap()
+        };
+
+        // s and t keep their own wrap modes.
+        match hit(0.0, 0.0).material {
+            MaterialE::Lambertian(m) => match m.albedo {
+                TextureE::Image(t) => {
+                    assert_eq!(t.image.width(), 2);
+                    assert_eq!(t.wrap, uvec2(WRAP_REPEAT, WRAP_CLAMP));
+                }
+                _ => panic!("expected an image texture"),
+            },
+            _ => panic!("expected a lambertian material"),
+        }
+
+        let albedo = |x: f32, y: f32| {
+            let h = hit(x, y);
+            h.material.albedo(&h)
+        };
+
+        // the red texel scaled by the factor on the left, the blue one on the right. the
+        // uvs come from the second set, with v flipped so the image stays upright.
+        assert_eq!(albedo(-0.5, -0.9), vec3(0.5, 0.0, 0.0));
+        assert_eq!(albedo(0.5, -0.9), vec3(0.0, 0.0, 1.0));
+
+        // the texture's uv set has to exist.
+        let no_uvs = TEXTURED.replace(r#", "TEXCOORD_1": 1"#, "");
+        assert!(matches!(
+            load_str(&dir, "no_uvs.gltf", &no_uvs),
+            Err(GltfError::MissingUvs { set: 1, .. })
+        ));
+    }
+}
@@ -1,8 +1,14 @@
-use std::f32::INFINITY;
+use std::f32::{consts::PI, INFINITY};
 
-use spirv_std::glam::Vec3;
+use spirv_std::glam::{vec2, vec3, Vec2, Vec3};
 
-use crate::{material::MaterialE, ray::Ray};
+use crate::{
+    bvh::{Aabb, Bvh},
+    material::MaterialE,
+    mesh::{Mesh, Triangle},
+    ray::Ray,
+    texture::Footprint,
+};
 
 pub struct Hit {
     pub position: Vec3,
@@ -10,31 +16,106 @@ pub struct Hit {
     pub t: f32,
     pub front_face: bool,
     pub material: MaterialE,
+    // barycentric coordinates of the second and third vertex for triangles, zero otherwise.
+    pub barycentric: Vec2,
+    // texture coordinates, the mesh's uvs or the barycentrics for triangles, longitude and
+    // latitude for spheres.
+    pub uv: Vec2,
+    // partial derivatives of the position along u and v, not normalized and not flipped
+    // with the normal.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // orthonormal basis around `normal` with the tangent along `dpdu`.
+    pub frame: Frame,
+    // position of the hit object in the outermost list or bvh, 0 for a lone primitive.
+    pub object_id: u32,
+    // area of the surface the ray's cone covers, what image textures are filtered over.
+    pub footprint: Footprint,
+}
+
+/// Orthonormal shading basis, local coordinates have the normal along z.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Frame {
+    /// `tangent` is projected onto the plane of `normal`, any direction in the plane is
+    /// used when it is (nearly) parallel to it.
+    pub fn new(normal: Vec3, tangent: Vec3) -> Self {
+        let t = tangent - normal * normal.dot(tangent);
+        let tangent = if t.length_squared() > 1e-12 {
+            t.normalize()
+        } else {
+            normal.any_orthonormal_vector()
+        };
+
+        Self {
+            tangent,
+            bitangent: normal.cross(tangent),
+            normal,
+        }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        vec3(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
 }
 
 pub trait Hitable {
     fn hit(&self, r: &Ray, t: Interval) -> Option<Hit>;
+    fn bounding_box(&self) -> Aabb;
+
+    /// shadow ray query, true if anything is hit within `t`.
+    fn occluded(&self, r: &Ray, t: Interval) -> bool {
+        self.hit(r, t).is_some()
+    }
 }
 
 #[derive(Clone)]
 pub enum HittableE {
     Sphere(Sphere),
+    Triangle(Triangle),
+    Mesh(Mesh),
     List(Vec<HittableE>),
+    Bvh(Bvh),
+}
+
+impl HittableE {
+    /// wraps a list of hittables in a bvh, hits are the same as for `HittableE::List`.
+    pub fn bvh(list: Vec<HittableE>) -> Self {
+        HittableE::Bvh(Bvh::new(list))
+    }
 }
 
 impl Hitable for HittableE {
     fn hit(&self, r: &Ray, t: Interval) -> Option<Hit> {
         match self {
             HittableE::Sphere(s) => s.hit(r, t),
+            HittableE::Triangle(tri) => tri.hit(r, t),
+            HittableE::Mesh(m) => m.hit(r, t),
             HittableE::List(l) => {
                 let mut closest = t.max;
                 let mut hit: Option<Hit> = None;
 
-                for h in l.iter() {
+                for (i, h) in l.iter().enumerate() {
                     match h.hit(r, Interval::new(t.min, closest)) {
                         Some(r) => {
                             closest = r.t;
-                            hit = Some(r);
+                            hit = Some(Hit {
+                                object_id: i as u32,
+                                ..r
+                            });
                         }
                         None => {}
                     }
@@ -42,11 +123,33 @@ impl Hitable for HittableE {
 
                 hit
             }
+            HittableE::Bvh(b) => b.hit(r, t),
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            HittableE::Sphere(s) => s.bounding_box(),
+            HittableE::Triangle(tri) => tri.bounding_box(),
+            HittableE::Mesh(m) => m.bounding_box(),
+            HittableE::List(l) => l
+                .iter()
+                .fold(Aabb::empty(), |b, h| b.union(&h.bounding_box())),
+            HittableE::Bvh(b) => b.bounding_box(),
+        }
+    }
+
+    fn occluded(&self, r: &Ray, t: Interval) -> bool {
+        match self {
+            HittableE::Mesh(m) => m.occluded(r, t),
+            HittableE::List(l) => l.iter().any(|h| h.occluded(r, t)),
+            HittableE::Bvh(b) => b.occluded(r, t),
+            _ => self.hit(r, t).is_some(),
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
@@ -94,16 +197,49 @@ impl Hitable for Sphere {
         let n = 2.0 * f32::from(front_face) - 1.0;
         let normal = outward_normal * n;
 
-        Some(Hit {
+        let (dpdu, dpdv) = sphere_dpduv(outward_normal, self.radius);
+
+        let mut h = Hit {
             position,
             normal,
             front_face,
             t: root,
-            material: self.material,
-        })
+            material: self.material.clone(),
+            barycentric: Vec2::ZERO,
+            uv: sphere_uv(outward_normal),
+            dpdu,
+            dpdv,
+            frame: Frame::new(normal, dpdu),
+            object_id: 0,
+            footprint: Footprint::default(),
+        };
+        h.footprint = Footprint::new(r, &h);
+        Some(h)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::splat(self.radius.abs());
+        Aabb::new(self.center - r, self.center + r)
     }
 }
 
+// u goes around the y axis starting at -x, v from the bottom pole to the top one.
+fn sphere_uv(n: Vec3) -> Vec2 {
+    let theta = (-n.y).clamp(-1.0, 1.0).acos();
+    let phi = (-n.z).atan2(n.x) + PI;
+    vec2(phi / (2.0 * PI), theta / PI)
+}
+
+// derivatives of `center + radius * n` along the uvs of `sphere_uv`. dpdu vanishes at the
+// poles and dpdv is kept finite there.
+fn sphere_dpduv(n: Vec3, radius: f32) -> (Vec3, Vec3) {
+    let sin_theta = (n.x * n.x + n.z * n.z).sqrt().max(1e-6);
+    let dpdu = 2.0 * PI * radius * vec3(n.z, 0.0, -n.x);
+    let dpdv = PI * radius * vec3(-n.x * n.y / sin_theta, sin_theta, -n.y * n.z / sin_theta);
+    (dpdu, dpdv)
+}
+
+#[derive(Copy, Clone)]
 pub struct Interval {
     pub min: f32,
     pub max: f32,
@@ -156,3 +292,59 @@ pub fn max(a: f32, b: f32) -> f32 {
         b
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash22;
+
+    // the derivatives have to match the positions the uvs step to.
+    #[test]
+    fn test_sphere_derivatives_match_uvs() {
+        let sphere = Sphere::new(vec3(0.5, -1.0, 2.0), 1.5, MaterialE::default());
+        let t = Interval::new(0.0, f32::INFINITY);
+
+        for i in 0..100 {
+            let d = hash22(vec2(i as f32, 0.3)) * 2.0 - 1.0;
+            let o = sphere.center + vec3(d.x, d.y, 5.0);
+            let h = sphere.hit(&Ray::new(o, sphere.center - o), t).unwrap();
+
+            let du = vec2(1e-3, 0.0);
+            let dv = vec2(0.0, 1e-3);
+            let p = |uv: Vec2| {
+                let (phi, theta) = (uv.x * 2.0 * PI, uv.y * PI);
+                let n = vec3(
+                    -theta.sin() * phi.cos(),
+                    -theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                sphere.center + sphere.radius * n
+            };
+
+            assert!(p(h.uv).distance(h.position) < 1e-4);
+            let dpdu = (p(h.uv + du) - p(h.uv - du)) / 2e-3;
+            let dpdv = (p(h.uv + dv) - p(h.uv - dv)) / 2e-3;
+            assert!(dpdu.distance(h.dpdu) < 1e-2 * h.dpdu.length().max(1.0));
+            assert!(dpdv.distance(h.dpdv) < 1e-2 * h.dpdv.length().max(1.0));
+
+            let f = h.frame;
+            assert_eq!(f.normal, h.normal);
+            assert!(f.tangent.dot(h.dpdu.normalize()) > 0.999);
+            assert!(f.tangent.cross(f.bitangent).distance(f.normal) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let n = vec3(0.2, 0.9, -0.4).normalize();
+        for tangent in [Vec3::X, n, Vec3::ZERO] {
+            let f = Frame::new(n, tangent);
+            assert!(f.tangent.dot(n).abs() < 1e-6);
+            assert!((f.tangent.length() - 1.0).abs() < 1e-5);
+            assert!(f.to_local(n).distance(Vec3::Z) < 1e-5);
+
+            let v = vec3(0.3, -1.2, 0.7);
+            assert!(f.to_world(f.to_local(v)).distance(v) < 1e-5);
+        }
+    }
+}
@@ -2,34 +2,184 @@ use std::f32::{consts::PI, INFINITY};
 
 use bytemuck::{Pod, Zeroable};
 
-use hittable::{Hitable, HittableE, Interval, Sphere};
-use material::{DialetricMaterial, LambertianMaterial, Material, MaterialE, MetalMaterial};
+use aov::AovPixel;
+use camera::Camera;
+use hittable::{Hit, Hitable, HittableE, Interval, Sphere};
+use light::LightE;
+use material::{
+    DialetricMaterial, DiffuseLightMaterial, LambertianMaterial, Material, MaterialE, MetalMaterial,
+};
+use mesh::Mesh;
 use ray::Ray;
+use sampler::Sampler;
+use texture::{Texture, TextureE};
 
-use spirv_std::glam::{mat3, uvec2, vec2, vec3, vec4, Mat3, UVec2, Vec3, Vec4, Vec4Swizzles};
+use spirv_std::glam::{mat3, uvec2, vec2, vec3, vec4, Mat3, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
 use util::{linear_to_gamma, linear_to_gamma_f32};
 
+pub mod aov;
+pub mod bvh;
+pub mod camera;
 pub mod color;
+pub mod denoise;
 pub mod depth;
+pub mod filter;
+pub mod gltf;
 pub mod hittable;
+pub mod light;
 pub mod material;
+pub mod mesh;
+pub mod mipmap;
+pub mod modes;
+pub mod obj;
 pub mod ray;
+pub mod rng;
+pub mod sampler;
+pub mod scene_file;
+pub mod texture;
+pub mod texture_file;
+pub mod tonemap;
 pub mod util;
 
-#[derive(Copy, Clone, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
 #[repr(C)]
 pub struct ShaderConstants {
     pub width: u32,
     pub height: u32,
     pub aa_stages: u32,
     pub bounce_limit: i32,
+    // depth that `depth::DOF_POST` keeps sharp.
     pub focus_point: f32,
+    // 1 to light the scene with the sky gradient, 0 for a black background.
+    pub sky: u32,
+    // how depth of field is rendered, one of the `depth::DOF_*` modes.
+    pub dof: u32,
+    // offsets the per pixel random sequences, renders with different seeds have independent noise.
+    pub seed: u32,
+    // `sampler::SAMPLER_*`, where the samples of a pixel come from.
+    pub sampler: u32,
+    // `filter::FILTER_*`, how samples are weighted into the pixels around them.
+    pub filter: u32,
+    // one of the `tonemap::TONEMAP_*` operators, applied before the output transform.
+    pub tonemap: u32,
+    // in stops, applied before tone mapping.
+    pub exposure: f32,
+    // luminance mapped to white by `TONEMAP_REINHARD_EXTENDED`.
+    pub white_point: f32,
+    // `color::WORKING_*`, the primaries of scene colors and linear output.
+    pub working_space: u32,
+    // `color::DISPLAY_*`, the encoding of 8 bit output.
+    pub display: u32,
+    // `denoise::DENOISE_*`, filter applied to the radiance before the post pass.
+    pub denoise: u32,
 }
 
-fn rt(sc: &ShaderConstants, r: Ray, world: &HittableE) -> Vec4 {
+impl Default for ShaderConstants {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            aa_stages: 100,
+            bounce_limit: 100,
+            focus_point: 30.0,
+            sky: 1,
+            dof: depth::DOF_POST,
+            seed: 0,
+            sampler: sampler::SAMPLER_SOBOL,
+            filter: filter::FILTER_BOX,
+            tonemap: tonemap::TONEMAP_NONE,
+            exposure: 0.0,
+            white_point: 4.0,
+            working_space: color::WORKING_REC709,
+            display: color::DISPLAY_SRGB,
+            denoise: denoise::DENOISE_NONE,
+        }
+    }
+}
+
+fn sky(sc: &ShaderConstants, r: &Ray) -> Vec3 {
+    if sc.sky == 0 {
+        return Vec3::ZERO;
+    }
+
+    let rdu = r.direction.normalize();
+    let a = 0.5 * (rdu.y + 1.0);
+    (1.0 - a) * vec3(1.0, 1.0, 1.0) + a * vec3(0.5, 0.7, 1.0)
+}
+
+/// Everything needed to render, the geometry, the emitters found in it and the camera.
+pub struct Scene {
+    pub world: HittableE,
+    pub lights: Vec<LightE>,
+    pub camera: Camera,
+}
+
+impl Scene {
+    pub fn new(world: HittableE, camera: Camera) -> Self {
+        let lights = light::collect_lights(&world);
+        Self {
+            world,
+            lights,
+            camera,
+        }
+    }
+}
+
+// light arriving at `h` straight from a sampled light, weighted against the chance of
+// the bsdf sample finding the same light.
+fn direct_light(scene: &Scene, r: &Ray, h: &Hit, sampler: &mut Sampler) -> Vec3 {
+    let u = sampler.next_vec3();
+    let wo = -r.direction.normalize();
+
+    let Some(ls) = light::sample_lights(&scene.lights, h.position, u) else {
+        return Vec3::ZERO;
+    };
+
+    let cos = ls.direction.dot(h.normal);
+    if cos <= 0.0 || ls.pdf <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let f = h.material.eval(h, ls.direction, wo);
+    if f == Vec3::ZERO {
+        return Vec3::ZERO;
+    }
+
+    let shadow = Ray::new(h.position, ls.direction);
+    let radiance = match ls.radiance {
+        TextureE::Constant(c) => {
+            if scene
+                .world
+                .occluded(&shadow, Interval::new(0.0001, ls.distance * 0.9999))
+            {
+                return Vec3::ZERO;
+            }
+            c
+        }
+        // a textured light has to be hit to know what it emits at the sampled point.
+        _ => match scene
+            .world
+            .hit(&shadow, Interval::new(0.0001, ls.distance * 1.0001))
+        {
+            Some(l) if l.t >= ls.distance * 0.9999 => match &l.material {
+                MaterialE::DiffuseLight(m) => m.radiance.value(&l),
+                _ => return Vec3::ZERO,
+            },
+            _ => return Vec3::ZERO,
+        },
+    };
+
+    let w = util::power_heuristic(ls.pdf, h.material.pdf(h, ls.direction, wo));
+    f * radiance * cos * w / ls.pdf
+}
+
+fn rt(sc: &ShaderConstants, r: Ray, scene: &Scene, sampler: &mut Sampler) -> Vec4 {
     let mut r = r;
-    let mut hit = world.hit(&r, Interval::new(0.0, INFINITY));
-    let mut color = Vec3::splat(1.0);
+    let mut hit = scene.world.hit(&r, Interval::new(0.0, INFINITY));
+
+    // radiance gathered so far and the attenuation of everything along the path.
+    let mut color = Vec3::ZERO;
+    let mut throughput = Vec3::splat(1.0);
 
     let mut d = 100.0;
 
@@ -42,6 +192,9 @@ fn rt(sc: &ShaderConstants, r: Ray, world: &HittableE) -> Vec4 {
     }
 
     let mut iter = 0;
+    // pdf of the bsdf sample that produced `r`, 0 for the camera ray and specular bounces
+    // which light sampling can't reproduce.
+    let mut bsdf_pdf = 0.0;
 
     loop {
         if iter > sc.bounce_limit {
@@ -50,29 +203,50 @@ fn rt(sc: &ShaderConstants, r: Ray, world: &HittableE) -> Vec4 {
 
         match &hit {
             Some(h) => {
-                let mat = h.material.scatter(&r, &h);
+                let mat = h.material.sample(&r, h, sampler);
+
+                if mat.emitted != Vec3::ZERO {
+                    let w = if bsdf_pdf > 0.0 {
+                        let wi = r.direction.normalize();
+                        let distance = r.origin.distance(h.position);
+                        let light_pdf = light::pdf_lights(&scene.lights, r.origin, wi, distance);
+                        util::power_heuristic(bsdf_pdf, light_pdf)
+                    } else {
+                        1.0
+                    };
+
+                    color += throughput * mat.emitted * w;
+                }
+
+                // specular lobes can't be lit by sampling a point on a light.
+                if mat.pdf > 0.0 && !scene.lights.is_empty() {
+                    color += throughput * direct_light(scene, &r, h, sampler);
+                }
+
                 match mat.ray {
                     Some(s) => {
-                        color *= 1.0 / mat.attenuation;
-                        hit = world.hit(&s, Interval::new(0.0001, INFINITY));
+                        // the cone carries on from the width it reached at the hit. rough
+                        // bounces spread it much further, but what they see is blurred
+                        // by the path's own noise anyway.
+                        let width = r.width_at(r.origin.distance(h.position));
+                        let s = s.with_cone(width, r.spread);
+
+                        throughput *= mat.attenuation;
+                        bsdf_pdf = mat.pdf;
+                        hit = scene.world.hit(&s, Interval::new(0.0001, INFINITY));
                         r = s;
                         iter += 1;
                     }
-                    None => {
-                        color *= 1.0 / mat.attenuation;
-                        break;
-                    }
+                    None => break,
                 }
             }
-            None => break,
+            None => {
+                color += throughput * sky(sc, &r);
+                break;
+            }
         }
     }
 
-    color = 1.0 / color;
-
-    let rdu = r.direction.normalize();
-    let a = 0.5 * (rdu.y + 1.0);
-    color = ((1.0 - a) * vec3(1.0, 1.0, 1.0) + a * vec3(0.5, 0.7, 1.0)) * color;
     vec4(color.x, color.y, color.z, d)
 }
 
@@ -84,37 +258,68 @@ pub fn set_camera(ro: Vec3, ta: Vec3, cr: f32) -> Mat3 {
     mat3(cu, cv, cw)
 }
 
-pub fn render_pass_one(sc: &ShaderConstants, world: &HittableE, idx: UVec2) -> Vec4 {
-    let time = 1.0; // right now we are not using time
-
-    let p = idx.as_vec2();
+pub fn render_pass_one(sc: &ShaderConstants, scene: &Scene, idx: UVec2) -> Vec4 {
+    render_pixel(sc, scene, idx, None)
+}
 
+/// `render_pass_one` that also fills in the aovs of the pixel when given. The samples are
+/// box filtered, only the pixel's own ones count whatever `sc.filter` is.
+pub fn render_pixel(
+    sc: &ShaderConstants,
+    scene: &Scene,
+    idx: UVec2,
+    mut aovs: Option<&mut AovPixel>,
+) -> Vec4 {
     let mut color = Vec4::splat(0.0);
 
-    // camera
-    let ta = vec3(0.0, 0.0, -1.0);
-    let ro = vec3(-2.0, 1.0, 1.0);
-    let cam = set_camera(ro, ta, 0.0);
-
     for i in 0..sc.aa_stages {
-        // calc uv and flipping uv.y
-        let mut uv =
-            ((2.0 * p - uvec2(sc.width, sc.height).as_vec2()) / sc.height as f32) * vec2(1.0, -1.);
-
-        let offset = i as f32 * idx.as_vec2();
-        let position = util::hash22(offset) - 0.5;
+        color += render_sample(sc, scene, idx, i, aovs.as_deref_mut()).color;
+    }
 
-        uv += position * 0.005;
+    if let Some(a) = aovs {
+        a.finish();
+    }
 
-        let focal_length = 4.0;
-        let rd = cam * vec3(uv.x, uv.y, focal_length).normalize();
+    color / sc.aa_stages as f32
+}
 
-        let seed = util::hash22(uv + (i as f32) * (time % 100.));
+/// One sample of a pixel.
+#[derive(Copy, Clone, Debug)]
+pub struct PixelSample {
+    /// where it was taken, in pixels from the center of the pixel.
+    pub offset: Vec2,
+    /// radiance with the depth in w.
+    pub color: Vec4,
+}
 
-        color += rt(sc, Ray::new(ro, rd, seed), &world);
+/// Sample `i` of the pixel at `idx`. Each index gives its own point in the pixel and path
+/// through `Sampler`, so samples can be taken in any number of batches. The aovs are added
+/// to but not finished.
+pub fn render_sample(
+    sc: &ShaderConstants,
+    scene: &Scene,
+    idx: UVec2,
+    i: u32,
+    aovs: Option<&mut AovPixel>,
+) -> PixelSample {
+    let mut sampler = Sampler::new(sc, idx, i);
+
+    // somewhere in the pixel, which spans half a pixel either side of `idx`.
+    let offset = sampler.next_vec2() - 0.5;
+    let p = idx.as_vec2() + offset;
+
+    // calc uv and flipping uv.y
+    let uv = ((2.0 * p - uvec2(sc.width, sc.height).as_vec2()) / sc.height as f32) * vec2(1.0, -1.);
+
+    let r = scene.camera.get_ray(sc, uv, &mut sampler);
+    if let Some(a) = aovs {
+        a.add(scene, &r);
     }
 
-    color / sc.aa_stages as f32
+    PixelSample {
+        offset,
+        color: rt(sc, r, scene, &mut sampler),
+    }
 }
 
 pub fn describe_scene() -> HittableE {
@@ -126,7 +331,7 @@ pub fn describe_scene() -> HittableE {
 
     let mat_right = MaterialE::Metal(MetalMaterial::new(vec3(0.8, 0.6, 0.2), 0.8));
 
-    HittableE::List(vec![
+    HittableE::bvh(vec![
         HittableE::Sphere(Sphere::new(Vec3::new(0.0, -100.5, -1.0), 100.0, mat_ground)),
         HittableE::Sphere(Sphere::new(Vec3::new(0.0, 0.0, -1.2), 0.5, mat_center)),
         HittableE::Sphere(Sphere::new(Vec3::new(-1.0, 0.0, -1.0), 0.5, mat_left)),
@@ -147,6 +352,68 @@ pub fn describe_scene2() -> HittableE {
     ])
 }
 
+/// closed room lit only by a light in the ceiling, render with `sky` set to 0.
+pub fn describe_cornell_box() -> HittableE {
+    let red = MaterialE::Lambertian(LambertianMaterial::new(vec3(0.65, 0.05, 0.05)));
+    let white = MaterialE::Lambertian(LambertianMaterial::new(vec3(0.73, 0.73, 0.73)));
+    let green = MaterialE::Lambertian(LambertianMaterial::new(vec3(0.12, 0.45, 0.15)));
+    let light = MaterialE::DiffuseLight(DiffuseLightMaterial::new(vec3(15.0, 15.0, 15.0)));
+
+    let mat_metal = MaterialE::Metal(MetalMaterial::new(vec3(0.8, 0.85, 0.88), 0.05));
+    let mat_glass = MaterialE::Dialetric(DialetricMaterial::new(vec3(1.0, 1.0, 1.0), 1.5));
+
+    let quad = |q: Vec3, u: Vec3, v: Vec3, m: MaterialE| HittableE::Mesh(Mesh::quad(q, u, v, m));
+
+    // the camera sits inside the room, looking down at the spheres.
+    let (x0, x1) = (-3.0, 2.0);
+    let (y0, y1) = (-0.5, 1.5);
+    let (z0, z1) = (-3.0, 2.0);
+    let (w, h, d) = (x1 - x0, y1 - y0, z1 - z0);
+
+    HittableE::bvh(vec![
+        quad(vec3(x0, y0, z0), vec3(0.0, h, 0.0), vec3(0.0, 0.0, d), red),
+        quad(
+            vec3(x1, y0, z0),
+            vec3(0.0, 0.0, d),
+            vec3(0.0, h, 0.0),
+            green,
+        ),
+        quad(
+            vec3(x0, y0, z0),
+            vec3(0.0, 0.0, d),
+            vec3(w, 0.0, 0.0),
+            white.clone(),
+        ),
+        quad(
+            vec3(x0, y1, z0),
+            vec3(w, 0.0, 0.0),
+            vec3(0.0, 0.0, d),
+            white.clone(),
+        ),
+        quad(
+            vec3(x0, y0, z0),
+            vec3(w, 0.0, 0.0),
+            vec3(0.0, h, 0.0),
+            white.clone(),
+        ),
+        quad(
+            vec3(x0, y0, z1),
+            vec3(0.0, h, 0.0),
+            vec3(w, 0.0, 0.0),
+            white.clone(),
+        ),
+        quad(
+            vec3(-1.0, y1 - 0.01, -1.5),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+            light,
+        ),
+        HittableE::Sphere(Sphere::new(vec3(-1.0, 0.0, -1.0), 0.5, mat_glass)),
+        HittableE::Sphere(Sphere::new(vec3(0.0, 0.0, -1.2), 0.5, white)),
+        HittableE::Sphere(Sphere::new(vec3(1.0, 0.0, -1.0), 0.5, mat_metal)),
+    ])
+}
+
 pub fn describe_scene3() -> HittableE {
     todo!()
 }
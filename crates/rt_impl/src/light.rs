@@ -0,0 +1,308 @@
+use std::f32::consts::PI;
+
+use spirv_std::glam::{Vec2, Vec3};
+
+use crate::{
+    hittable::{Hitable, HittableE, Interval, Sphere},
+    material::MaterialE,
+    mesh::intersect_triangle,
+    ray::Ray,
+    texture::TextureE,
+};
+
+/// Direction and radiance towards a point sampled on a light, `pdf` is per solid angle
+/// as seen from the shading point and already includes picking the light.
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    // only a constant can be used as is, other textures need the hit on the light.
+    pub radiance: TextureE,
+    pub pdf: f32,
+}
+
+pub trait Light {
+    fn sample(&self, p: Vec3, u: Vec2) -> Option<LightSample>;
+
+    /// distance to the light along the unit direction `wi` and the solid angle pdf of
+    /// `sample` having picked that point.
+    fn pdf(&self, p: Vec3, wi: Vec3) -> Option<(f32, f32)>;
+}
+
+#[derive(Clone)]
+pub enum LightE {
+    Sphere(SphereLight),
+    Triangle(TriangleLight),
+}
+
+impl Light for LightE {
+    fn sample(&self, p: Vec3, u: Vec2) -> Option<LightSample> {
+        match self {
+            LightE::Sphere(l) => l.sample(p, u),
+            LightE::Triangle(l) => l.sample(p, u),
+        }
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> Option<(f32, f32)> {
+        match self {
+            LightE::Sphere(l) => l.pdf(p, wi),
+            LightE::Triangle(l) => l.pdf(p, wi),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SphereLight {
+    pub center: Vec3,
+    pub radius: f32,
+    pub radiance: TextureE,
+}
+
+impl Light for SphereLight {
+    // samples the cone of directions the sphere subtends, which unlike area sampling
+    // never wastes samples on the back of the sphere.
+    fn sample(&self, p: Vec3, u: Vec2) -> Option<LightSample> {
+        let to_center = self.center - p;
+        let dist2 = to_center.length_squared();
+        let r2 = self.radius * self.radius;
+
+        // shading points inside the light are lit from every direction already.
+        if dist2 <= r2 {
+            return None;
+        }
+
+        let w = to_center / dist2.sqrt();
+        let (tu, tv) = w.any_orthonormal_pair();
+
+        let cos_max = (1.0 - r2 / dist2).max(0.0).sqrt();
+        let cos_theta = 1.0 - u.x * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+
+        let direction = (tu * phi.cos() + tv * phi.sin()) * sin_theta + w * cos_theta;
+
+        let sphere = Sphere::new(self.center, self.radius, MaterialE::default());
+        let r = Ray::new(p, direction);
+        // grazing directions can numerically miss, the tangent distance is close enough.
+        let distance = sphere
+            .hit(&r, Interval::new(0.0, f32::INFINITY))
+            .map_or((dist2 - r2).sqrt(), |h| h.t);
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance.clone(),
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+        })
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> Option<(f32, f32)> {
+        let dist2 = (self.center - p).length_squared();
+        let r2 = self.radius * self.radius;
+        if dist2 <= r2 {
+            return None;
+        }
+
+        let sphere = Sphere::new(self.center, self.radius, MaterialE::default());
+        let h = sphere.hit(&Ray::new(p, wi), Interval::new(0.0, f32::INFINITY))?;
+
+        let cos_max = (1.0 - r2 / dist2).max(0.0).sqrt();
+        Some((h.t, 1.0 / (2.0 * PI * (1.0 - cos_max))))
+    }
+}
+
+#[derive(Clone)]
+pub struct TriangleLight {
+    pub v0: Vec3,
+    pub v1: Vec3,
+    pub v2: Vec3,
+    pub radiance: TextureE,
+}
+
+impl Light for TriangleLight {
+    fn sample(&self, p: Vec3, u: Vec2) -> Option<LightSample> {
+        let e1 = self.v1 - self.v0;
+        let e2 = self.v2 - self.v0;
+        let n = e1.cross(e2);
+        let area = 0.5 * n.length();
+
+        if area <= 0.0 {
+            return None;
+        }
+
+        // uniform point on the triangle.
+        let su = u.x.sqrt();
+        let q = self.v0 + e1 * (su * (1.0 - u.y)) + e2 * (su * u.y);
+
+        let to_light = q - p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+
+        // lights emit from both sides.
+        let cos_light = n.normalize().dot(direction).abs();
+        if cos_light <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance.clone(),
+            pdf: distance * distance / (cos_light * area),
+        })
+    }
+
+    fn pdf(&self, p: Vec3, wi: Vec3) -> Option<(f32, f32)> {
+        let r = Ray::new(p, wi);
+        let (t, _) = intersect_triangle(
+            &r,
+            self.v0,
+            self.v1,
+            self.v2,
+            &Interval::new(0.0, f32::INFINITY),
+        )?;
+
+        let n = (self.v1 - self.v0).cross(self.v2 - self.v0);
+        let area = 0.5 * n.length();
+        let cos_light = n.normalize().dot(wi).abs();
+        if area <= 0.0 || cos_light <= 0.0 {
+            return None;
+        }
+
+        Some((t, t * t / (cos_light * area)))
+    }
+}
+
+/// Finds every emissive primitive in the scene.
+pub fn collect_lights(world: &HittableE) -> Vec<LightE> {
+    let mut lights = vec![];
+    collect(world, &mut lights);
+    lights
+}
+
+fn collect(h: &HittableE, lights: &mut Vec<LightE>) {
+    match h {
+        HittableE::Sphere(s) => {
+            if let MaterialE::DiffuseLight(m) = &s.material {
+                lights.push(LightE::Sphere(SphereLight {
+                    center: s.center,
+                    radius: s.radius.abs(),
+                    radiance: m.radiance.clone(),
+                }));
+            }
+        }
+        HittableE::Triangle(t) => {
+            if let MaterialE::DiffuseLight(m) = &t.material {
+                lights.push(LightE::Triangle(TriangleLight {
+                    v0: t.v0,
+                    v1: t.v1,
+                    v2: t.v2,
+                    radiance: m.radiance.clone(),
+                }));
+            }
+        }
+        HittableE::Mesh(mesh) => {
+            if let MaterialE::DiffuseLight(m) = &mesh.material {
+                for i in mesh.indices.iter() {
+                    lights.push(LightE::Triangle(TriangleLight {
+                        v0: mesh.positions[i.x as usize],
+                        v1: mesh.positions[i.y as usize],
+                        v2: mesh.positions[i.z as usize],
+                        radiance: m.radiance.clone(),
+                    }));
+                }
+            }
+        }
+        HittableE::List(l) => l.iter().for_each(|h| collect(h, lights)),
+        HittableE::Bvh(b) => b.primitives.iter().for_each(|h| collect(h, lights)),
+    }
+}
+
+/// Picks one light uniformly and samples a point on it, `u.x` selects the light and
+/// `u.yz` the point.
+pub fn sample_lights(lights: &[LightE], p: Vec3, u: Vec3) -> Option<LightSample> {
+    if lights.is_empty() {
+        return None;
+    }
+
+    let n = lights.len();
+    let i = ((u.x * n as f32) as usize).min(n - 1);
+
+    let mut s = lights[i].sample(p, Vec2::new(u.y, u.z))?;
+    s.pdf /= n as f32;
+
+    Some(s)
+}
+
+/// Density of `sample_lights` picking the point at `distance` along the unit direction `wi`.
+pub fn pdf_lights(lights: &[LightE], p: Vec3, wi: Vec3, distance: f32) -> f32 {
+    if lights.is_empty() {
+        return 0.0;
+    }
+
+    // only the light that was actually hit counts, not others further along the ray.
+    let eps = 1e-3 * distance.max(1.0);
+    let pdf: f32 = lights
+        .iter()
+        .filter_map(|l| l.pdf(p, wi))
+        .filter(|(t, _)| (t - distance).abs() < eps)
+        .map(|(_, pdf)| pdf)
+        .sum();
+
+    pdf / lights.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash32;
+    use spirv_std::glam::{vec2, vec3};
+
+    #[test]
+    fn test_sphere_light_irradiance() {
+        // irradiance under a sphere light straight overhead is pi * L * (r / d)^2.
+        let light = SphereLight {
+            center: vec3(0.0, 4.0, 0.0),
+            radius: 0.5,
+            radiance: Vec3::splat(10.0).into(),
+        };
+        let expected = PI * 10.0 * (0.5f32 / 4.0).powi(2);
+
+        let n = 20000;
+        let irradiance = (0..n)
+            .filter_map(|i| {
+                let u = hash32(vec2(i as f32, 0.5));
+                light.sample(Vec3::ZERO, vec2(u.x, u.y))
+            })
+            .map(|s| {
+                assert_eq!(s.radiance, TextureE::Constant(Vec3::splat(10.0)));
+                10.0 * s.direction.y.max(0.0) / s.pdf
+            })
+            .sum::<f32>()
+            / n as f32;
+
+        assert!((irradiance - expected).abs() / expected < 0.02);
+    }
+
+    #[test]
+    fn test_triangle_light_points_are_on_triangle() {
+        let light = TriangleLight {
+            v0: vec3(-1.0, 2.0, -1.0),
+            v1: vec3(1.0, 2.0, -1.0),
+            v2: vec3(0.0, 2.0, 1.0),
+            radiance: Vec3::ONE.into(),
+        };
+
+        for i in 0..1000 {
+            let u = hash32(vec2(i as f32, 0.25));
+            let s = light.sample(Vec3::ZERO, vec2(u.x, u.y)).unwrap();
+            let q = s.direction * s.distance;
+
+            assert!((q.y - 2.0).abs() < 1e-4);
+            assert!(s.pdf > 0.0);
+
+            let (t, pdf) = light.pdf(Vec3::ZERO, s.direction).unwrap();
+            assert!((t - s.distance).abs() < 1e-4);
+            assert!((pdf - s.pdf).abs() / s.pdf < 1e-3);
+        }
+    }
+}
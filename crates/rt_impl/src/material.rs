@@ -1,21 +1,43 @@
+use std::f32::consts::PI;
+
 use spirv_std::glam::Vec3;
 
 use crate::{
     hittable::Hit,
     ray::Ray,
-    util::{self, hash22},
+    sampler::Sampler,
+    texture::{Texture, TextureE},
+    util,
 };
 
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> MatResult;
+    /// samples the next ray of the path, `MatResult::pdf` is 0 for specular (delta) lobes.
+    fn sample(&self, r_in: &Ray, hit: &Hit, sampler: &mut Sampler) -> MatResult;
+
+    /// brdf for light arriving from `wi` and leaving along `wo`, both pointing away from the
+    /// surface. Always zero for specular lobes since they can't be hit by chance.
+    fn eval(&self, _hit: &Hit, _wi: Vec3, _wo: Vec3) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    /// solid angle density of `sample` picking `wi`.
+    fn pdf(&self, _hit: &Hit, _wi: Vec3, _wo: Vec3) -> f32 {
+        0.0
+    }
+
+    /// surface color at `hit`, written to the albedo aov.
+    fn albedo(&self, _hit: &Hit) -> Vec3 {
+        Vec3::ZERO
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum MaterialE {
     Default(DefaultMaterial),
     Lambertian(LambertianMaterial),
     Metal(MetalMaterial),
     Dialetric(DialetricMaterial),
+    DiffuseLight(DiffuseLightMaterial),
 }
 
 impl Default for MaterialE {
@@ -25,123 +47,252 @@ impl Default for MaterialE {
 }
 
 impl Material for MaterialE {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> MatResult {
+    fn sample(&self, r_in: &Ray, hit: &Hit, sampler: &mut Sampler) -> MatResult {
+        match self {
+            MaterialE::Default(m) => m.sample(r_in, hit, sampler),
+            MaterialE::Lambertian(m) => m.sample(r_in, hit, sampler),
+            MaterialE::Metal(m) => m.sample(r_in, hit, sampler),
+            MaterialE::Dialetric(m) => m.sample(r_in, hit, sampler),
+            MaterialE::DiffuseLight(m) => m.sample(r_in, hit, sampler),
+        }
+    }
+
+    fn eval(&self, hit: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        match self {
+            MaterialE::Default(m) => m.eval(hit, wi, wo),
+            MaterialE::Lambertian(m) => m.eval(hit, wi, wo),
+            MaterialE::Metal(m) => m.eval(hit, wi, wo),
+            MaterialE::Dialetric(m) => m.eval(hit, wi, wo),
+            MaterialE::DiffuseLight(m) => m.eval(hit, wi, wo),
+        }
+    }
+
+    fn pdf(&self, hit: &Hit, wi: Vec3, wo: Vec3) -> f32 {
         match self {
-            MaterialE::Default(m) => m.scatter(r_in, hit),
-            MaterialE::Lambertian(m) => m.scatter(r_in, hit),
-            MaterialE::Metal(m) => m.scatter(r_in, hit),
-            MaterialE::Dialetric(m) => m.scatter(r_in, hit),
+            MaterialE::Default(m) => m.pdf(hit, wi, wo),
+            MaterialE::Lambertian(m) => m.pdf(hit, wi, wo),
+            MaterialE::Metal(m) => m.pdf(hit, wi, wo),
+            MaterialE::Dialetric(m) => m.pdf(hit, wi, wo),
+            MaterialE::DiffuseLight(m) => m.pdf(hit, wi, wo),
         }
     }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        match self {
+            MaterialE::Default(m) => m.albedo(hit),
+            MaterialE::Lambertian(m) => m.albedo(hit),
+            MaterialE::Metal(m) => m.albedo(hit),
+            MaterialE::Dialetric(m) => m.albedo(hit),
+            MaterialE::DiffuseLight(m) => m.albedo(hit),
+        }
+    }
+}
+
+impl MaterialE {
+    /// id derived from the type and parameters of the material, equal materials share it.
+    /// Kept to 24 bits so it survives being stored as a float.
+    pub fn id(&self) -> u32 {
+        let (tag, color, param) = match self {
+            MaterialE::Default(m) => (1, &m.albedo, 0),
+            MaterialE::Lambertian(m) => (2, &m.albedo, 0),
+            MaterialE::Metal(m) => (3, &m.albedo, m.fuzz.hash(0)),
+            MaterialE::Dialetric(m) => (4, &m.albedo, m.refractive_index.hash(0)),
+            MaterialE::DiffuseLight(m) => (5, &m.radiance, 0),
+        };
+
+        let h = util::hash(color.hash(util::hash(tag)) ^ param);
+        h & 0xff_ffff
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct DefaultMaterial {
-    pub albedo: Vec3,
+    pub albedo: TextureE,
 }
 
 impl Default for DefaultMaterial {
     fn default() -> Self {
         Self {
-            albedo: Vec3::splat(0.5),
+            albedo: Vec3::splat(0.5).into(),
         }
     }
 }
 
 impl Material for DefaultMaterial {
-    fn scatter(&self, _r_in: &Ray, _hit: &Hit) -> MatResult {
+    fn sample(&self, _r_in: &Ray, hit: &Hit, _sampler: &mut Sampler) -> MatResult {
         MatResult {
             ray: None,
-            attenuation: self.albedo,
+            attenuation: self.albedo.value(hit),
+            emitted: Vec3::ZERO,
+            pdf: 0.0,
         }
     }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct LambertianMaterial {
-    pub albedo: Vec3,
+    pub albedo: TextureE,
 }
 
 impl LambertianMaterial {
-    pub fn new(albedo: Vec3) -> Self {
-        Self { albedo }
+    pub fn new(albedo: impl Into<TextureE>) -> Self {
+        Self {
+            albedo: albedo.into(),
+        }
     }
 }
 
 impl Default for LambertianMaterial {
     fn default() -> Self {
-        Self {
-            albedo: Vec3::splat(0.5),
-        }
+        Self::new(Vec3::splat(0.5))
     }
 }
 
 impl Material for LambertianMaterial {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> MatResult {
-        // let dir = hit.normal + util::random_in_unit_sphere(r_in.seed);
-        let dir = util::random_on_hemisphere(hit.normal, r_in.seed);
-        let ray = Ray::new(hit.position, dir, hash22(r_in.seed * 1.0012032));
+    fn sample(&self, r_in: &Ray, hit: &Hit, sampler: &mut Sampler) -> MatResult {
+        let dir = util::cosine_on_hemisphere(hit.normal, sampler.next_vec2());
+        let ray = Ray::new(hit.position, dir);
 
+        // cosine sampling cancels the cosine and 1 / pi of the brdf.
         MatResult {
             ray: Some(ray),
-            attenuation: self.albedo,
+            attenuation: self.albedo.value(hit),
+            emitted: Vec3::ZERO,
+            pdf: self.pdf(hit, dir, -r_in.direction),
         }
     }
+
+    fn eval(&self, hit: &Hit, wi: Vec3, _wo: Vec3) -> Vec3 {
+        if wi.dot(hit.normal) > 0.0 {
+            self.albedo.value(hit) / PI
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    fn pdf(&self, hit: &Hit, wi: Vec3, _wo: Vec3) -> f32 {
+        wi.normalize().dot(hit.normal).max(0.0) / PI
+    }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct MetalMaterial {
-    pub fuzz: f32,
-    pub albedo: Vec3,
+    // in [0, 1], grey textures give it directly, colored ones by the mean of the channels.
+    pub fuzz: TextureE,
+    pub albedo: TextureE,
 }
 
 impl MetalMaterial {
-    pub fn new(albedo: Vec3, fuzz: f32) -> Self {
-        Self { albedo, fuzz }
+    pub fn new(albedo: impl Into<TextureE>, fuzz: impl Into<TextureE>) -> Self {
+        Self {
+            albedo: albedo.into(),
+            fuzz: fuzz.into(),
+        }
+    }
+
+    fn fuzz(&self, hit: &Hit) -> f32 {
+        let f = self.fuzz.value(hit);
+        ((f.x + f.y + f.z) / 3.0).clamp(0.0, 1.0)
+    }
+
+    // fuzz is turned into the exponent of a phong lobe around the mirror direction,
+    // the inverse of the mapping used for mtl `Ns`.
+    fn exponent(fuzz: f32) -> f32 {
+        (2.0 / (fuzz * fuzz) - 2.0).max(0.0)
+    }
+
+    fn mirror(hit: &Hit, wo: Vec3) -> Vec3 {
+        util::reflect(-wo.normalize(), hit.normal)
     }
 }
 
 impl Default for MetalMaterial {
     fn default() -> Self {
-        Self {
-            albedo: Vec3::splat(0.5),
-            fuzz: 0.0,
-        }
+        Self::new(Vec3::splat(0.5), 0.0)
     }
 }
 
 impl Material for MetalMaterial {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> MatResult {
-        let rfl = util::reflect(r_in.direction, hit.normal).normalize()
-            + (self.fuzz * util::random_in_unit_sphere(r_in.seed * 1.029838));
+    fn sample(&self, r_in: &Ray, hit: &Hit, sampler: &mut Sampler) -> MatResult {
+        let mirror = MetalMaterial::mirror(hit, -r_in.direction);
+
+        let (rfl, pdf) = if self.fuzz(hit) > 0.0 {
+            let dir = util::phong_lobe(mirror, Self::exponent(self.fuzz(hit)), sampler.next_vec2());
+            (dir, self.pdf(hit, dir, -r_in.direction))
+        } else {
+            (mirror, 0.0)
+        };
 
+        // samples that end up below the surface are absorbed.
         let ray = if rfl.dot(hit.normal) > 0.0 {
-            Some(Ray::new(hit.position, rfl, hash22(r_in.seed * 1.0012032)))
+            Some(Ray::new(hit.position, rfl))
         } else {
             None
         };
 
         MatResult {
             ray,
-            attenuation: self.albedo,
+            attenuation: self.albedo.value(hit),
+            emitted: Vec3::ZERO,
+            pdf,
         }
     }
+
+    // chosen so that eval * cos / pdf is exactly the albedo, like the sampled rays.
+    fn eval(&self, hit: &Hit, wi: Vec3, wo: Vec3) -> Vec3 {
+        let cos_i = wi.normalize().dot(hit.normal);
+        if self.fuzz(hit) <= 0.0 || cos_i <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        self.albedo.value(hit) * self.pdf(hit, wi, wo) / cos_i
+    }
+
+    fn pdf(&self, hit: &Hit, wi: Vec3, wo: Vec3) -> f32 {
+        let fuzz = self.fuzz(hit);
+        if fuzz <= 0.0 {
+            return 0.0;
+        }
+
+        let n = Self::exponent(fuzz);
+        let cos_a = wi.normalize().dot(MetalMaterial::mirror(hit, wo)).max(0.0);
+        (n + 1.0) / (2.0 * PI) * cos_a.powf(n)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct DialetricMaterial {
-    pub albedo: Vec3,
-    pub refractive_index: f32,
+    pub albedo: TextureE,
+    pub refractive_index: TextureE,
 }
 
 impl DialetricMaterial {
-    pub fn new(albedo: Vec3, refractive_index: f32) -> Self {
+    pub fn new(albedo: impl Into<TextureE>, refractive_index: impl Into<TextureE>) -> Self {
         Self {
-            albedo,
-            refractive_index,
+            albedo: albedo.into(),
+            refractive_index: refractive_index.into(),
         }
     }
 
+    // the mean of the channels like `MetalMaterial::fuzz`, kept above 0 where a texture
+    // goes dark.
+    fn refractive_index(&self, hit: &Hit) -> f32 {
+        let ior = self.refractive_index.value(hit);
+        ((ior.x + ior.y + ior.z) / 3.0).max(0.01)
+    }
+
     fn reflectance(cosine: f32, refraction_index: f32) -> f32 {
         // Use Schlick's approximation for reflectance.
         let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
@@ -151,20 +302,14 @@ impl DialetricMaterial {
 
 impl Default for DialetricMaterial {
     fn default() -> Self {
-        Self {
-            albedo: Vec3::splat(0.5),
-            refractive_index: 1.5,
-        }
+        Self::new(Vec3::splat(0.5), 1.5)
     }
 }
 
 impl Material for DialetricMaterial {
-    fn scatter(&self, r: &Ray, h: &Hit) -> MatResult {
-        let ri = if h.front_face {
-            1.0 / self.refractive_index
-        } else {
-            self.refractive_index
-        };
+    fn sample(&self, r: &Ray, h: &Hit, sampler: &mut Sampler) -> MatResult {
+        let ior = self.refractive_index(h);
+        let ri = if h.front_face { 1.0 / ior } else { ior };
 
         let unit_direction = r.direction.normalize();
         let cos_theta = (-unit_direction).dot(h.normal).min(1.0);
@@ -172,7 +317,7 @@ impl Material for DialetricMaterial {
 
         let cannot_refract = ri * sin_theta > 1.0;
         let direction = if cannot_refract
-            || DialetricMaterial::reflectance(cos_theta, ri) > util::rand_f32(r.seed.x)
+            || DialetricMaterial::reflectance(cos_theta, ri) > sampler.next_f32()
         {
             util::reflect(unit_direction, h.normal)
         } else {
@@ -180,13 +325,174 @@ impl Material for DialetricMaterial {
         };
 
         MatResult {
-            ray: Some(Ray::new(h.position, direction, hash22(r.seed * 1.0012032))),
-            attenuation: self.albedo,
+            ray: Some(Ray::new(h.position, direction)),
+            attenuation: self.albedo.value(h),
+            emitted: Vec3::ZERO,
+            pdf: 0.0,
+        }
+    }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit)
+    }
+}
+
+#[derive(Clone)]
+pub struct DiffuseLightMaterial {
+    pub radiance: TextureE,
+}
+
+impl DiffuseLightMaterial {
+    pub fn new(radiance: impl Into<TextureE>) -> Self {
+        Self {
+            radiance: radiance.into(),
         }
     }
 }
 
+impl Default for DiffuseLightMaterial {
+    fn default() -> Self {
+        Self::new(Vec3::splat(1.0))
+    }
+}
+
+impl Material for DiffuseLightMaterial {
+    fn sample(&self, _r_in: &Ray, hit: &Hit, _sampler: &mut Sampler) -> MatResult {
+        // emits from both sides, so the winding of light geometry doesn't matter.
+        MatResult {
+            ray: None,
+            attenuation: Vec3::ZERO,
+            emitted: self.radiance.value(hit),
+            pdf: 0.0,
+        }
+    }
+
+    // denoisers expect emitters to look like a bright surface.
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.radiance.value(hit).min(Vec3::ONE)
+    }
+}
+
 pub struct MatResult {
     pub ray: Option<Ray>,
     pub attenuation: Vec3,
+    // radiance emitted by the surface towards the incoming ray.
+    pub emitted: Vec3,
+    // solid angle pdf of `ray`, 0 when it came from a specular lobe.
+    pub pdf: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Frame,
+        texture::{CheckerTexture, Footprint, CHECKER_UV},
+        ShaderConstants,
+    };
+    use spirv_std::glam::{uvec2, vec2, vec3};
+
+    fn sampler(pixel: u32) -> Sampler {
+        Sampler::new(&ShaderConstants::default(), uvec2(pixel, 0), 0)
+    }
+
+    fn hit(material: MaterialE) -> Hit {
+        Hit {
+            position: Vec3::ZERO,
+            normal: vec3(0.0, 1.0, 0.0),
+            t: 1.0,
+            front_face: true,
+            material,
+            barycentric: vec2(0.0, 0.0),
+            uv: vec2(0.0, 0.0),
+            dpdu: Vec3::X,
+            dpdv: Vec3::Z,
+            frame: Frame::new(vec3(0.0, 1.0, 0.0), Vec3::X),
+            object_id: 0,
+            footprint: Footprint::default(),
+        }
+    }
+
+    // the sampled weight has to match eval * cos / pdf or mis would be biased.
+    #[test]
+    fn test_sample_matches_eval_and_pdf() {
+        let materials = [
+            MaterialE::Lambertian(LambertianMaterial::new(vec3(0.8, 0.5, 0.2))),
+            MaterialE::Metal(MetalMaterial::new(vec3(0.9, 0.9, 0.9), 0.3)),
+            MaterialE::Metal(MetalMaterial::new(vec3(0.9, 0.9, 0.9), 1.0)),
+        ];
+
+        for m in materials {
+            let h = hit(m.clone());
+            for i in 0..1000 {
+                let r_in = Ray::new(vec3(-1.0, 1.0, 0.0), vec3(1.0, -1.0, 0.0));
+                let s = m.sample(&r_in, &h, &mut sampler(i));
+                let Some(ray) = s.ray else { continue };
+
+                let wo = -r_in.direction.normalize();
+                let wi = ray.direction.normalize();
+                let cos = wi.dot(h.normal);
+                assert!((s.pdf - m.pdf(&h, wi, wo)).abs() < 1e-3 * s.pdf.max(1.0));
+
+                let weight = m.eval(&h, wi, wo) * cos / s.pdf;
+                assert!((weight - s.attenuation).abs().max_element() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_textured_fuzz_and_radiance() {
+        // 0 in the cell at u < 0.5, 1 in the next one.
+        let checker =
+            TextureE::Checker(CheckerTexture::new(Vec3::ZERO, Vec3::ONE, 0.5, CHECKER_UV));
+        let metal = MaterialE::Metal(MetalMaterial::new(vec3(0.9, 0.9, 0.9), checker.clone()));
+        let light = MaterialE::DiffuseLight(DiffuseLightMaterial::new(checker));
+        let at = |m: &MaterialE, u| Hit {
+            uv: vec2(u, 0.25),
+            ..hit(m.clone())
+        };
+
+        // a mirror where the fuzz is 0 can't be hit by chance, a rough lobe can.
+        let (wi, wo) = (
+            vec3(1.0, 1.0, 0.2).normalize(),
+            vec3(-1.0, 1.0, 0.0).normalize(),
+        );
+        assert_eq!(metal.pdf(&at(&metal, 0.25), wi, wo), 0.0);
+        assert!(metal.pdf(&at(&metal, 0.75), wi, wo) > 0.0);
+
+        let r = Ray::new(Vec3::Y, Vec3::NEG_Y);
+        let emitted = |u| light.sample(&r, &at(&light, u), &mut sampler(0)).emitted;
+        assert_eq!(emitted(0.25), Vec3::ZERO);
+        assert_eq!(emitted(0.75), Vec3::ONE);
+    }
+
+    #[test]
+    fn test_textured_refractive_index() {
+        // 1 in the cell at u < 0.5, 3 in the next one.
+        let checker = TextureE::Checker(CheckerTexture::new(
+            Vec3::ONE,
+            Vec3::splat(3.0),
+            0.5,
+            CHECKER_UV,
+        ));
+        let glass = DialetricMaterial::new(Vec3::ONE, checker);
+        let at = |u, front_face| Hit {
+            uv: vec2(u, 0.25),
+            front_face,
+            ..hit(MaterialE::Dialetric(glass.clone()))
+        };
+        assert_eq!(glass.refractive_index(&at(0.25, true)), 1.0);
+        assert_eq!(glass.refractive_index(&at(0.75, true)), 3.0);
+        assert_eq!(
+            DialetricMaterial::new(Vec3::ONE, 1.5).refractive_index(&at(0.25, true)),
+            1.5
+        );
+
+        // leaving the denser cell at 45 degrees is past its critical angle.
+        let r = Ray::new(vec3(-1.0, 1.0, 0.0), vec3(1.0, -1.0, 0.0));
+        for i in 0..100 {
+            let s = glass.sample(&r, &at(0.75, false), &mut sampler(i));
+            assert!(s.ray.unwrap().direction.y > 0.0);
+        }
+    }
 }
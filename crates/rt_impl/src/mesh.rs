@@ -0,0 +1,522 @@
+use std::fmt;
+
+use spirv_std::glam::{vec2, UVec3, Vec2, Vec3};
+
+use crate::{
+    bvh::{self, Aabb, BvhNode},
+    hittable::{Frame, Hit, Hitable, Interval},
+    material::MaterialE,
+    ray::Ray,
+    texture::Footprint,
+};
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub v0: Vec3,
+    pub v1: Vec3,
+    pub v2: Vec3,
+    pub material: MaterialE,
+}
+
+impl Triangle {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: MaterialE) -> Self {
+        Self {
+            v0,
+            v1,
+            v2,
+            material,
+        }
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, r: &Ray, t: Interval) -> Option<Hit> {
+        let p = [self.v0, self.v1, self.v2];
+        let (root, b) = intersect_triangle(r, p[0], p[1], p[2], &t)?;
+
+        Some(triangle_hit(
+            r,
+            root,
+            b,
+            p,
+            None,
+            BARYCENTRIC_UVS,
+            &self.material,
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::empty().grow(self.v0).grow(self.v1).grow(self.v2)
+    }
+}
+
+/// Why `Mesh::new` rejected its buffers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeshError {
+    // `normals` or `uvs` is neither empty nor one per position.
+    AttributeCount {
+        attribute: &'static str,
+        count: usize,
+        positions: usize,
+    },
+    IndexOutOfRange {
+        index: u32,
+        positions: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::AttributeCount {
+                attribute,
+                count,
+                positions,
+            } => write!(
+                f,
+                "{} {} must be empty or match the {} positions",
+                count, attribute, positions
+            ),
+            MeshError::IndexOutOfRange { index, positions } => {
+                write!(
+                    f,
+                    "index {} out of range for {} positions",
+                    index, positions
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+/// Indexed triangle mesh, all triangles share the vertex buffers and a single material.
+/// `normals` and `uvs` are optional and are either empty or the same length as `positions`.
+#[derive(Clone)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<UVec3>,
+    pub material: MaterialE,
+    // triangles are reordered to match the leaves of the bvh.
+    nodes: Vec<BvhNode>,
+    order: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Vec2>,
+        indices: Vec<UVec3>,
+        material: MaterialE,
+    ) -> Result<Self, MeshError> {
+        for (attribute, count) in [("normals", normals.len()), ("uvs", uvs.len())] {
+            if count != 0 && count != positions.len() {
+                return Err(MeshError::AttributeCount {
+                    attribute,
+                    count,
+                    positions: positions.len(),
+                });
+            }
+        }
+        if let Some(i) = indices
+            .iter()
+            .find(|i| i.max_element() as usize >= positions.len())
+        {
+            return Err(MeshError::IndexOutOfRange {
+                index: i.max_element(),
+                positions: positions.len(),
+            });
+        }
+
+        Ok(Self::build(positions, normals, uvs, indices, material))
+    }
+
+    // `new` without the checks, for buffers that are valid by construction.
+    fn build(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Vec2>,
+        indices: Vec<UVec3>,
+        material: MaterialE,
+    ) -> Self {
+        let bounds: Vec<Aabb> = indices
+            .iter()
+            .map(|i| {
+                Aabb::empty()
+                    .grow(positions[i.x as usize])
+                    .grow(positions[i.y as usize])
+                    .grow(positions[i.z as usize])
+            })
+            .collect();
+
+        let (nodes, order) = bvh::build(&bounds);
+        let indices = order.iter().map(|o| indices[*o as usize]).collect();
+
+        Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+            nodes,
+            order,
+        }
+    }
+
+    /// parallelogram with corner `q` spanned by `u` and `v`, facing `u x v`.
+    pub fn quad(q: Vec3, u: Vec3, v: Vec3, material: MaterialE) -> Self {
+        Self::build(
+            vec![q, q + u, q + u + v, q + v],
+            vec![],
+            vec![
+                vec2(0.0, 0.0),
+                vec2(1.0, 0.0),
+                vec2(1.0, 1.0),
+                vec2(0.0, 1.0),
+            ],
+            vec![UVec3::new(0, 1, 2), UVec3::new(0, 2, 3)],
+            material,
+        )
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn hit_triangle(&self, r: &Ray, i: usize, t: Interval) -> Option<Hit> {
+        let idx = self.indices[i];
+        let (p0, p1, p2) = (
+            self.positions[idx.x as usize],
+            self.positions[idx.y as usize],
+            self.positions[idx.z as usize],
+        );
+
+        let (root, b) = intersect_triangle(r, p0, p1, p2, &t)?;
+
+        let ns = if self.normals.is_empty() {
+            None
+        } else {
+            Some(
+                (1.0 - b.x - b.y) * self.normals[idx.x as usize]
+                    + b.x * self.normals[idx.y as usize]
+                    + b.y * self.normals[idx.z as usize],
+            )
+        };
+
+        let uvs = if self.uvs.is_empty() {
+            BARYCENTRIC_UVS
+        } else {
+            [
+                self.uvs[idx.x as usize],
+                self.uvs[idx.y as usize],
+                self.uvs[idx.z as usize],
+            ]
+        };
+
+        Some(triangle_hit(
+            r,
+            root,
+            b,
+            [p0, p1, p2],
+            ns,
+            uvs,
+            &self.material,
+        ))
+    }
+}
+
+impl Hitable for Mesh {
+    fn hit(&self, r: &Ray, t: Interval) -> Option<Hit> {
+        bvh::traverse(&self.nodes, &self.order, r, &t, |i, t| {
+            self.hit_triangle(r, i, t)
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    fn occluded(&self, r: &Ray, t: Interval) -> bool {
+        bvh::any_hit(&self.nodes, &self.order, r, &t, |i, t| {
+            let idx = self.indices[i];
+            let p = |j: u32| self.positions[j as usize];
+            intersect_triangle(r, p(idx.x), p(idx.y), p(idx.z), &t).is_some()
+        })
+    }
+}
+
+// uvs of triangles without any, so u and v are the barycentrics of the second and third
+// vertex.
+const BARYCENTRIC_UVS: [Vec2; 3] = [Vec2::ZERO, Vec2::X, Vec2::Y];
+
+// `ns` is the interpolated shading normal, the geometric one is used without it or when it
+// interpolates to zero. the shading normal is flipped onto the same side as the geometric
+// one before both are turned to face the incoming ray.
+fn triangle_hit(
+    r: &Ray,
+    root: f32,
+    b: Vec2,
+    p: [Vec3; 3],
+    ns: Option<Vec3>,
+    uvs: [Vec2; 3],
+    material: &MaterialE,
+) -> Hit {
+    let ng = (p[1] - p[0]).cross(p[2] - p[0]).normalize();
+    let ns = match ns {
+        Some(n) if n.length_squared() > 0.0 => n.normalize(),
+        _ => ng,
+    };
+
+    let front_face = r.direction.dot(ng) < 0.0;
+    let ns = if ns.dot(ng) < 0.0 { -ns } else { ns };
+
+    // invert normal if we are inside
+    let n = 2.0 * f32::from(front_face) - 1.0;
+    let normal = ns * n;
+
+    let (dpdu, dpdv) = triangle_dpduv(p, uvs, ng);
+
+    let mut h = Hit {
+        position: r.origin + (r.direction * root),
+        normal,
+        t: root,
+        front_face,
+        material: material.clone(),
+        barycentric: b,
+        uv: (1.0 - b.x - b.y) * uvs[0] + b.x * uvs[1] + b.y * uvs[2],
+        dpdu,
+        dpdv,
+        frame: Frame::new(normal, dpdu),
+        object_id: 0,
+        footprint: Footprint::default(),
+    };
+    h.footprint = Footprint::new(r, &h);
+    h
+}
+
+// solves the edges for the position derivatives, the uvs of a degenerate mapping (all on
+// a line) are replaced by any pair of directions in the plane.
+fn triangle_dpduv(p: [Vec3; 3], uv: [Vec2; 3], ng: Vec3) -> (Vec3, Vec3) {
+    let (dp02, dp12) = (p[0] - p[2], p[1] - p[2]);
+    let (duv02, duv12) = (uv[0] - uv[2], uv[1] - uv[2]);
+    let det = duv02.x * duv12.y - duv02.y * duv12.x;
+
+    if det.abs() < 1e-9 {
+        return ng.any_orthonormal_pair();
+    }
+
+    let dpdu = (duv12.y * dp02 - duv02.y * dp12) / det;
+    let dpdv = (duv02.x * dp12 - duv12.x * dp02) / det;
+    (dpdu, dpdv)
+}
+
+fn permute(v: Vec3, kx: usize, ky: usize, kz: usize) -> Vec3 {
+    Vec3::new(v[kx], v[ky], v[kz])
+}
+
+/// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013).
+/// Returns the ray parameter and the barycentrics of `p1` and `p2`.
+pub fn intersect_triangle(
+    r: &Ray,
+    p0: Vec3,
+    p1: Vec3,
+    p2: Vec3,
+    t: &Interval,
+) -> Option<(f32, Vec2)> {
+    // shear and permute so the ray points along +z from the origin.
+    let da = r.direction.abs();
+    let kz = if da.x > da.y {
+        if da.x > da.z {
+            0
+        } else {
+            2
+        }
+    } else if da.y > da.z {
+        1
+    } else {
+        2
+    };
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+
+    let d = permute(r.direction, kx, ky, kz);
+    if d.z == 0.0 {
+        return None;
+    }
+
+    let mut p0t = permute(p0 - r.origin, kx, ky, kz);
+    let mut p1t = permute(p1 - r.origin, kx, ky, kz);
+    let mut p2t = permute(p2 - r.origin, kx, ky, kz);
+
+    let sx = -d.x / d.z;
+    let sy = -d.y / d.z;
+    let sz = 1.0 / d.z;
+
+    p0t.x += sx * p0t.z;
+    p0t.y += sy * p0t.z;
+    p1t.x += sx * p1t.z;
+    p1t.y += sy * p1t.z;
+    p2t.x += sx * p2t.z;
+    p2t.y += sy * p2t.z;
+
+    let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+    let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+    let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+    // edges that land exactly on zero are recomputed in double precision so that
+    // rays through a shared edge hit exactly one of the two triangles.
+    if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+        e0 = (p1t.x as f64 * p2t.y as f64 - p1t.y as f64 * p2t.x as f64) as f32;
+        e1 = (p2t.x as f64 * p0t.y as f64 - p2t.y as f64 * p0t.x as f64) as f32;
+        e2 = (p0t.x as f64 * p1t.y as f64 - p0t.y as f64 * p1t.x as f64) as f32;
+    }
+
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t_scaled = (e0 * p0t.z + e1 * p1t.z + e2 * p2t.z) * sz;
+    let root = t_scaled / det;
+
+    if !t.surrounds(root) {
+        return None;
+    }
+
+    Some((root, vec2(e1 / det, e2 / det)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash22;
+    use spirv_std::glam::{uvec3, vec3};
+
+    fn quad() -> Mesh {
+        Mesh::new(
+            vec![
+                vec3(-1.0, -1.0, 0.0),
+                vec3(1.0, -1.0, 0.0),
+                vec3(1.0, 1.0, 0.0),
+                vec3(-1.0, 1.0, 0.0),
+            ],
+            vec![],
+            vec![],
+            vec![uvec3(0, 1, 2), uvec3(0, 2, 3)],
+            MaterialE::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_new_rejects_bad_buffers() {
+        let positions = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+        let m = MaterialE::default();
+
+        let r = Mesh::new(positions.clone(), vec![Vec3::Z], vec![], vec![], m.clone());
+        assert_eq!(
+            r.err(),
+            Some(MeshError::AttributeCount {
+                attribute: "normals",
+                count: 1,
+                positions: 3
+            })
+        );
+
+        let r = Mesh::new(
+            positions.clone(),
+            vec![],
+            vec![Vec2::ZERO; 4],
+            vec![],
+            m.clone(),
+        );
+        assert!(matches!(
+            r,
+            Err(MeshError::AttributeCount {
+                attribute: "uvs",
+                ..
+            })
+        ));
+
+        let r = Mesh::new(positions, vec![], vec![], vec![uvec3(0, 1, 3)], m);
+        assert_eq!(
+            r.err(),
+            Some(MeshError::IndexOutOfRange {
+                index: 3,
+                positions: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_mesh_is_watertight() {
+        let mesh = quad();
+
+        // rays aimed exactly at the shared diagonal must never slip through.
+        for i in 0..1000 {
+            let s = i as f32 / 1000.0 * 1.8 - 0.9;
+            let o = vec3(0.3, -0.2, 2.0);
+            let d = vec3(s, s, 0.0) - o;
+            let r = Ray::new(o, d);
+
+            assert!(mesh.hit(&r, Interval::new(0.0, f32::INFINITY)).is_some());
+            assert!(mesh.occluded(&r, Interval::new(0.0, f32::INFINITY)));
+            // the quad is at t = 1, past the end of a shorter shadow ray.
+            assert!(!mesh.occluded(&r, Interval::new(0.0, 0.5)));
+        }
+    }
+
+    #[test]
+    fn test_barycentrics_reconstruct_position() {
+        let (p0, p1, p2) = (
+            vec3(0.0, 0.0, -1.0),
+            vec3(1.0, 0.0, -1.0),
+            vec3(0.0, 1.0, -2.0),
+        );
+        let tri = Triangle::new(p0, p1, p2, MaterialE::default());
+
+        for i in 0..100 {
+            let b = hash22(vec2(i as f32, 3.7)) * 0.5;
+            let target = (1.0 - b.x - b.y) * p0 + b.x * p1 + b.y * p2;
+            let r = Ray::new(Vec3::ZERO, target);
+
+            let h = tri.hit(&r, Interval::new(0.0, f32::INFINITY)).unwrap();
+            let p = (1.0 - h.barycentric.x - h.barycentric.y) * p0
+                + h.barycentric.x * p1
+                + h.barycentric.y * p2;
+
+            assert!((h.t - 1.0).abs() < 1e-5);
+            assert!(p.distance(h.position) < 1e-5);
+            assert!(h.front_face);
+        }
+    }
+
+    #[test]
+    fn test_quad_derivatives_follow_its_edges() {
+        let (q, u, v) = (
+            vec3(1.0, 0.0, 2.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(0.0, 0.5, -1.0),
+        );
+        let mesh = Mesh::quad(q, u, v, MaterialE::default());
+        let t = Interval::new(0.0, f32::INFINITY);
+
+        // one ray into each triangle.
+        for target in [q + 0.7 * u + 0.2 * v, q + 0.2 * u + 0.7 * v] {
+            let o = target + u.cross(v);
+            let h = mesh.hit(&Ray::new(o, target - o), t).unwrap();
+
+            assert!(h.dpdu.distance(u) < 1e-5);
+            assert!(h.dpdv.distance(v) < 1e-5);
+            assert!(h.frame.tangent.distance(u.normalize()) < 1e-5);
+            assert!(h.frame.normal.distance(u.cross(v).normalize()) < 1e-5);
+        }
+    }
+}
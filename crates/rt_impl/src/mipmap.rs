@@ -0,0 +1,438 @@
+use spirv_std::glam::{vec2, UVec2, Vec2, Vec3};
+
+// values for `ImageTexture::wrap`, how lookups outside the unit uv square are handled. the
+// two axes of an image can wrap differently.
+pub const WRAP_REPEAT: u32 = 0;
+pub const WRAP_CLAMP: u32 = 1;
+// repeats, flipping every other copy so the edges line up.
+pub const WRAP_MIRROR: u32 = 2;
+
+// values for `ImageTexture::filter`.
+// the closest texel of the full size image.
+pub const MIP_NEAREST: u32 = 0;
+// the 4 closest texels of the full size image, aliases when minified.
+pub const MIP_BILINEAR: u32 = 1;
+// bilinear in the two levels around the footprint's longest axis, blurry at grazing angles.
+pub const MIP_TRILINEAR: u32 = 2;
+// gaussian over the elliptical footprint, sharp at grazing angles as well.
+pub const MIP_EWA: u32 = 3;
+
+// the ellipse is widened until it's at most this much longer than it is wide, so grazing
+// footprints don't loop over whole rows of the image.
+const MAX_ANISOTROPY: f32 = 8.0;
+// falloff of the ewa gaussian, exp(-alpha * r^2) over the unit ellipse.
+const EWA_ALPHA: f32 = 2.0;
+
+/// Linear texels of one level of a mipmap, rows stored top down.
+#[derive(Clone, Debug)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<Vec3>,
+}
+
+impl MipLevel {
+    pub fn new(width: u32, height: u32, texels: Vec<Vec3>) -> Self {
+        assert!(width > 0 && height > 0);
+        assert_eq!(texels.len(), (width * height) as usize);
+        Self {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    fn texel(&self, x: i32, y: i32, wrap: UVec2) -> Vec3 {
+        let w = self.width as i32;
+        let x = wrap_index(x, w, wrap.x);
+        let y = wrap_index(y, self.height as i32, wrap.y);
+        self.texels[(y * w + x) as usize]
+    }
+
+    // half the size, rounded down. every texel is the mean of the area it covers, so for
+    // odd sizes the texels it only partly covers count for that part.
+    fn downsample(&self) -> MipLevel {
+        let (w, h) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let (xs, ys) = (box_weights(self.width, w), box_weights(self.height, h));
+
+        let mut texels = Vec::with_capacity((w * h) as usize);
+        for (y0, wy) in ys.iter() {
+            for (x0, wx) in xs.iter() {
+                let mut sum = Vec3::ZERO;
+                for (dy, fy) in wy.iter().enumerate() {
+                    let row = (*y0 as usize + dy) * self.width as usize + *x0 as usize;
+                    for (dx, fx) in wx.iter().enumerate() {
+                        sum += self.texels[row + dx] * (fx * fy);
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+
+        MipLevel::new(w, h, texels)
+    }
+
+    // continuous texel coordinates of `uv`, v points up the image.
+    fn st(&self, uv: Vec2) -> Vec2 {
+        vec2(uv.x * self.width as f32, (1.0 - uv.y) * self.height as f32)
+    }
+
+    fn bilinear(&self, uv: Vec2, wrap: UVec2) -> Vec3 {
+        let st = self.st(uv) - 0.5;
+        let (x, y) = (st.x.floor() as i32, st.y.floor() as i32);
+        let f = st - st.floor();
+
+        let top = self.texel(x, y, wrap).lerp(self.texel(x + 1, y, wrap), f.x);
+        let bottom = self
+            .texel(x, y + 1, wrap)
+            .lerp(self.texel(x + 1, y + 1, wrap), f.x);
+        top.lerp(bottom, f.y)
+    }
+
+    // Heckbert's elliptically weighted average, as in pbrt. `axis0` and `axis1` span the
+    // footprint in uv.
+    fn ewa(&self, uv: Vec2, axis0: Vec2, axis1: Vec2, wrap: UVec2) -> Vec3 {
+        let st = self.st(uv) - 0.5;
+        // in texels, flipped along with v.
+        let size = vec2(self.width as f32, -(self.height as f32));
+        let (d0, d1) = (axis0 * size, axis1 * size);
+
+        // implicit ellipse a s^2 + b s t + c t^2 < 1, grown by a texel so it never falls
+        // between texel centers.
+        let a = d0.y * d0.y + d1.y * d1.y + 1.0;
+        let b = -2.0 * (d0.x * d0.y + d1.x * d1.y);
+        let c = d0.x * d0.x + d1.x * d1.x + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+
+        // bounding box of the ellipse.
+        let det = 4.0 * a * c - b * b;
+        let s_extent = 2.0 * (det * c).sqrt() / det;
+        let t_extent = 2.0 * (det * a).sqrt() / det;
+        let (s0, s1) = (
+            (st.x - s_extent).ceil() as i32,
+            (st.x + s_extent).floor() as i32,
+        );
+        let (t0, t1) = (
+            (st.y - t_extent).ceil() as i32,
+            (st.y + t_extent).floor() as i32,
+        );
+
+        let mut sum = Vec3::ZERO;
+        let mut weights = 0.0;
+        for t in t0..=t1 {
+            let tt = t as f32 - st.y;
+            for s in s0..=s1 {
+                let ss = s as f32 - st.x;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let w = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum += self.texel(s, t, wrap) * w;
+                    weights += w;
+                }
+            }
+        }
+
+        if weights > 0.0 {
+            sum / weights
+        } else {
+            self.bilinear(uv, wrap)
+        }
+    }
+}
+
+// for each of `dst` texels spread over `src` along one axis, the first source texel it
+// touches and the weights of the ones from there, the share of each it covers.
+fn box_weights(src: u32, dst: u32) -> Vec<(u32, Vec<f32>)> {
+    let (src, dst) = (src as u64, dst as u64);
+    (0..dst)
+        .map(|i| {
+            // in units of 1 / dst of a source texel, so the overlaps are exact.
+            let (lo, hi) = (i * src, (i + 1) * src);
+            let first = lo / dst;
+            let weights = (first..hi.div_ceil(dst))
+                .map(|j| (hi.min((j + 1) * dst) - lo.max(j * dst)) as f32 / src as f32)
+                .collect();
+            (first as u32, weights)
+        })
+        .collect()
+}
+
+fn wrap_index(i: i32, n: i32, wrap: u32) -> i32 {
+    match wrap {
+        WRAP_CLAMP => i.clamp(0, n - 1),
+        WRAP_MIRROR => mirror(i, n),
+        _ => i.rem_euclid(n),
+    }
+}
+
+fn mirror(i: i32, n: i32) -> i32 {
+    let i = i.rem_euclid(2 * n);
+    if i < n {
+        i
+    } else {
+        2 * n - 1 - i
+    }
+}
+
+/// An image and its successively halved copies down to a single texel, for looking up
+/// the image over a footprint without aliasing.
+#[derive(Clone, Debug)]
+pub struct MipMap {
+    // the full size image first.
+    pub levels: Vec<MipLevel>,
+}
+
+impl MipMap {
+    pub fn new(image: MipLevel) -> Self {
+        let mut levels = vec![image];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            levels.push(last.downsample());
+        }
+        Self { levels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    /// the mean of the whole image, its last level.
+    pub fn mean(&self) -> Vec3 {
+        self.levels[self.levels.len() - 1].texels[0]
+    }
+
+    /// The color over the footprint around `uv` spanned by `axis0` and `axis1`, with one
+    /// of the `MIP_*` filters.
+    pub fn lookup(&self, filter: u32, uv: Vec2, axis0: Vec2, axis1: Vec2, wrap: UVec2) -> Vec3 {
+        match filter {
+            MIP_NEAREST => self.nearest(uv, wrap),
+            MIP_BILINEAR => self.levels[0].bilinear(uv, wrap),
+            MIP_TRILINEAR => self.trilinear(uv, axis0.length().max(axis1.length()), wrap),
+            _ => self.ewa(uv, axis0, axis1, wrap),
+        }
+    }
+
+    /// The texel under `uv`.
+    pub fn nearest(&self, uv: Vec2, wrap: UVec2) -> Vec3 {
+        let level = &self.levels[0];
+        let st = level.st(uv).floor();
+        level.texel(st.x as i32, st.y as i32, wrap)
+    }
+
+    /// Bilinear lookups in the two levels whose texels are closest to `width` wide,
+    /// blended.
+    pub fn trilinear(&self, uv: Vec2, width: f32, wrap: UVec2) -> Vec3 {
+        let lod = self.lod(width);
+        let last = self.levels.len() - 1;
+        if lod <= 0.0 {
+            return self.levels[0].bilinear(uv, wrap);
+        }
+        if lod >= last as f32 {
+            return self.levels[last].bilinear(uv, wrap);
+        }
+
+        let i = lod.floor() as usize;
+        let a = self.levels[i].bilinear(uv, wrap);
+        let b = self.levels[i + 1].bilinear(uv, wrap);
+        a.lerp(b, lod - i as f32)
+    }
+
+    /// Gaussian weighted average over the ellipse with the axes `axis0` and `axis1`,
+    /// taken in the levels that fit its minor axis.
+    pub fn ewa(&self, uv: Vec2, axis0: Vec2, axis1: Vec2, wrap: UVec2) -> Vec3 {
+        let (major, mut minor) = if axis0.length_squared() < axis1.length_squared() {
+            (axis1, axis0)
+        } else {
+            (axis0, axis1)
+        };
+        let (major_length, mut minor_length) = (major.length(), minor.length());
+
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor *= scale;
+            minor_length *= scale;
+        }
+        if minor_length == 0.0 {
+            return self.levels[0].bilinear(uv, wrap);
+        }
+
+        let lod = self.lod(minor_length).max(0.0);
+        let i = lod.floor() as usize;
+        let last = self.levels.len() - 1;
+        if i >= last {
+            // the footprint covers the whole image.
+            return self.levels[last].texels[0];
+        }
+
+        let a = self.levels[i].ewa(uv, major, minor, wrap);
+        let b = self.levels[i + 1].ewa(uv, major, minor, wrap);
+        a.lerp(b, lod - i as f32)
+    }
+
+    // level whose texels are `width` wide in uv, fractional between levels. odd sizes round
+    // down, so each level is measured by its own size rather than as a power of two.
+    fn lod(&self, width: f32) -> f32 {
+        let texel = |i: usize| 1.0 / self.levels[i].width.max(self.levels[i].height) as f32;
+        let width = width.max(1e-8);
+
+        let last = self.levels.len() - 1;
+        if width <= texel(0) {
+            return (width / texel(0)).log2();
+        }
+        for i in 0..last {
+            let (a, b) = (texel(i), texel(i + 1));
+            if width < b {
+                return i as f32 + (width / a).log2() / (b / a).log2();
+            }
+        }
+        last as f32 + (width / texel(last)).log2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash22;
+    use spirv_std::glam::{uvec2, vec3};
+
+    // 8x4 checkerboard of single texels.
+    fn checkers() -> MipMap {
+        let texels = (0..32)
+            .map(|i| Vec3::splat(((i % 8 + i / 8) % 2) as f32))
+            .collect();
+        MipMap::new(MipLevel::new(8, 4, texels))
+    }
+
+    #[test]
+    fn test_pyramid_keeps_the_mean() {
+        // odd sizes share the texels that straddle two of the smaller level.
+        let texels = (0..35)
+            .map(|i| hash22(vec2(i as f32, 1.0)).extend(0.5))
+            .collect::<Vec<_>>();
+        let mean = texels.iter().sum::<Vec3>() / 35.0;
+        let mip = MipMap::new(MipLevel::new(7, 5, texels));
+
+        let sizes: Vec<_> = mip.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(7, 5), (3, 2), (1, 1)]);
+        for level in mip.levels.iter() {
+            let level_mean = level.texels.iter().sum::<Vec3>() / level.texels.len() as f32;
+            assert!(level_mean.distance(mean) < 1e-5);
+        }
+        assert_eq!(checkers().levels.len(), 4);
+
+        // each level is found by its own texel size, not as a power of two of the first.
+        assert!(mip.lod(1.0 / 7.0).abs() < 1e-5);
+        assert!((mip.lod(1.0 / 3.0) - 1.0).abs() < 1e-5);
+        assert!((mip.lod(1.0) - 2.0).abs() < 1e-5);
+        assert!((mip.lod(1.0 / 14.0) + 1.0).abs() < 1e-5);
+        let between = mip.lod(0.2);
+        assert!(between > 0.0 && between < 1.0);
+    }
+
+    #[test]
+    fn test_wrap_and_bilinear() {
+        let mip = MipMap::new(MipLevel::new(2, 1, vec![Vec3::ZERO, Vec3::ONE]));
+        let level = &mip.levels[0];
+
+        assert_eq!(
+            mip.nearest(vec2(0.25, 0.5), UVec2::splat(WRAP_REPEAT)),
+            Vec3::ZERO
+        );
+        assert_eq!(
+            mip.nearest(vec2(1.75, 0.5), UVec2::splat(WRAP_REPEAT)),
+            Vec3::ONE
+        );
+        assert_eq!(
+            mip.nearest(vec2(-0.75, 0.5), UVec2::splat(WRAP_REPEAT)),
+            Vec3::ZERO
+        );
+        assert_eq!(
+            mip.nearest(vec2(-0.75, 0.5), UVec2::splat(WRAP_CLAMP)),
+            Vec3::ZERO
+        );
+        assert_eq!(
+            mip.nearest(vec2(1.75, 0.5), UVec2::splat(WRAP_CLAMP)),
+            Vec3::ONE
+        );
+        assert_eq!(
+            mip.nearest(vec2(1.25, 0.5), UVec2::splat(WRAP_MIRROR)),
+            Vec3::ONE
+        );
+        assert_eq!(
+            mip.nearest(vec2(-0.25, 0.5), UVec2::splat(WRAP_MIRROR)),
+            Vec3::ZERO
+        );
+        assert_eq!(
+            mip.nearest(vec2(2.25, 0.5), UVec2::splat(WRAP_MIRROR)),
+            Vec3::ZERO
+        );
+        // u and v wrap on their own.
+        assert_eq!(
+            mip.nearest(vec2(1.25, -3.0), uvec2(WRAP_REPEAT, WRAP_CLAMP)),
+            Vec3::ZERO
+        );
+        assert_eq!(
+            mip.nearest(vec2(1.25, -3.0), uvec2(WRAP_CLAMP, WRAP_REPEAT)),
+            Vec3::ONE
+        );
+
+        // halfway between the texel centers.
+        assert_eq!(
+            level.bilinear(vec2(0.5, 0.5), UVec2::splat(WRAP_CLAMP)),
+            Vec3::splat(0.5)
+        );
+        // past the last center it blends back into the first texel or stays put.
+        assert_eq!(
+            level.bilinear(vec2(1.0, 0.5), UVec2::splat(WRAP_REPEAT)),
+            Vec3::splat(0.5)
+        );
+        assert_eq!(
+            level.bilinear(vec2(1.0, 0.5), UVec2::splat(WRAP_CLAMP)),
+            Vec3::ONE
+        );
+    }
+
+    #[test]
+    fn test_minified_lookups_average_the_footprint() {
+        let mip = checkers();
+        // the center of a texel.
+        let uv = vec2(2.5 / 8.0, 1.0 - 1.5 / 4.0);
+
+        // footprints much smaller than a texel see the texel.
+        let tiny = vec2(1e-4, 0.0);
+        let texel = mip.nearest(uv, UVec2::splat(WRAP_REPEAT));
+        for filter in [MIP_TRILINEAR, MIP_EWA] {
+            let c = mip.lookup(filter, uv, tiny, tiny.perp(), UVec2::splat(WRAP_REPEAT));
+            assert!(c.distance(texel) < 1e-3, "{filter} {c}");
+        }
+
+        // a footprint covering the image averages it.
+        let wide = vec2(2.0, 0.0);
+        for filter in [MIP_TRILINEAR, MIP_EWA] {
+            let c = mip.lookup(filter, uv, wide, wide.perp(), UVec2::splat(WRAP_REPEAT));
+            assert!(c.distance(Vec3::splat(0.5)) < 1e-3, "{filter} {c}");
+        }
+
+        // a grazing footprint, long along u and a texel across in v, still averages the
+        // checkers.
+        let long = vec2(1.0, 0.0);
+        let across = vec2(0.0, 0.25);
+        let c = mip.lookup(MIP_EWA, uv, long, across, UVec2::splat(WRAP_REPEAT));
+        assert!(c.distance(Vec3::splat(0.5)) < 0.1, "{c}");
+
+        // the filters agree on a constant image.
+        let flat = MipMap::new(MipLevel::new(4, 4, vec![vec3(0.2, 0.4, 0.6); 16]));
+        for filter in [MIP_NEAREST, MIP_BILINEAR, MIP_TRILINEAR, MIP_EWA] {
+            let c = flat.lookup(filter, uv, long, across, UVec2::splat(WRAP_CLAMP));
+            assert!(c.distance(vec3(0.2, 0.4, 0.6)) < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use serde::Deserialize;
+
+use crate::{
+    color::{
+        DISPLAY_GAMMA2, DISPLAY_P3, DISPLAY_REC709, DISPLAY_SRGB, WORKING_ACESCG, WORKING_REC709,
+    },
+    depth::{DOF_NONE, DOF_POST, DOF_THIN_LENS},
+    filter::{
+        FILTER_BLACKMAN_HARRIS, FILTER_BOX, FILTER_GAUSSIAN, FILTER_LANCZOS, FILTER_MITCHELL,
+        FILTER_TENT,
+    },
+    sampler::{
+        SAMPLER_BLUE_NOISE, SAMPLER_HALTON, SAMPLER_INDEPENDENT, SAMPLER_SOBOL, SAMPLER_STRATIFIED,
+    },
+    tonemap::{
+        TONEMAP_ACES, TONEMAP_AGX, TONEMAP_HABLE, TONEMAP_NONE, TONEMAP_REINHARD,
+        TONEMAP_REINHARD_EXTENDED,
+    },
+};
+
+// names for the `ShaderConstants` modes, shared by scene files and the command line. scene
+// files spell them in snake case, the command line in kebab case.
+
+/// `ShaderConstants::dof`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Dof {
+    None,
+    /// blur the finished image based on depth
+    Post,
+    /// trace rays through a thin lens
+    Lens,
+}
+
+impl Dof {
+    pub fn mode(self) -> u32 {
+        match self {
+            Dof::None => DOF_NONE,
+            Dof::Post => DOF_POST,
+            Dof::Lens => DOF_THIN_LENS,
+        }
+    }
+}
+
+/// `ShaderConstants::sampler`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Sampler {
+    /// a fresh random number for every dimension
+    Independent,
+    /// jittered strata, needs the sample count up front
+    Stratified,
+    Halton,
+    /// owen scrambled sobol
+    Sobol,
+    /// random numbers spread out over the screen
+    BlueNoise,
+}
+
+impl Sampler {
+    pub fn mode(self) -> u32 {
+        match self {
+            Sampler::Independent => SAMPLER_INDEPENDENT,
+            Sampler::Stratified => SAMPLER_STRATIFIED,
+            Sampler::Halton => SAMPLER_HALTON,
+            Sampler::Sobol => SAMPLER_SOBOL,
+            Sampler::BlueNoise => SAMPLER_BLUE_NOISE,
+        }
+    }
+}
+
+/// `ShaderConstants::filter`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    /// only the pixel's own samples, all weighted the same
+    Box,
+    /// 1 pixel radius
+    Tent,
+    /// 1.5 pixel radius
+    Gaussian,
+    /// mitchell-netravali, 2 pixel radius, slightly sharpening
+    Mitchell,
+    /// 2 pixel radius, sharp but can ring around edges
+    Lanczos,
+    /// 1.5 pixel radius
+    BlackmanHarris,
+}
+
+impl Filter {
+    pub fn mode(self) -> u32 {
+        match self {
+            Filter::Box => FILTER_BOX,
+            Filter::Tent => FILTER_TENT,
+            Filter::Gaussian => FILTER_GAUSSIAN,
+            Filter::Mitchell => FILTER_MITCHELL,
+            Filter::Lanczos => FILTER_LANCZOS,
+            Filter::BlackmanHarris => FILTER_BLACKMAN_HARRIS,
+        }
+    }
+}
+
+/// `ShaderConstants::tonemap`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMap {
+    None,
+    Reinhard,
+    ReinhardExtended,
+    Aces,
+    Agx,
+    Hable,
+}
+
+impl ToneMap {
+    pub fn mode(self) -> u32 {
+        match self {
+            ToneMap::None => TONEMAP_NONE,
+            ToneMap::Reinhard => TONEMAP_REINHARD,
+            ToneMap::ReinhardExtended => TONEMAP_REINHARD_EXTENDED,
+            ToneMap::Aces => TONEMAP_ACES,
+            ToneMap::Agx => TONEMAP_AGX,
+            ToneMap::Hable => TONEMAP_HABLE,
+        }
+    }
+}
+
+/// `ShaderConstants::working_space`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum WorkingSpace {
+    Rec709,
+    Acescg,
+}
+
+impl WorkingSpace {
+    pub fn mode(self) -> u32 {
+        match self {
+            WorkingSpace::Rec709 => WORKING_REC709,
+            WorkingSpace::Acescg => WORKING_ACESCG,
+        }
+    }
+}
+
+/// `ShaderConstants::display`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Display {
+    Srgb,
+    Rec709,
+    /// display p3
+    P3,
+    /// rec.709 primaries with a square root, the renderer's old output
+    Gamma2,
+}
+
+impl Display {
+    pub fn mode(self) -> u32 {
+        match self {
+            Display::Srgb => DISPLAY_SRGB,
+            Display::Rec709 => DISPLAY_REC709,
+            Display::P3 => DISPLAY_P3,
+            Display::Gamma2 => DISPLAY_GAMMA2,
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use spirv_std::glam::{uvec3, vec2, vec3, UVec3, Vec2, Vec3};
+
+use crate::{
+    hittable::HittableE,
+    material::{
+        DialetricMaterial, DiffuseLightMaterial, LambertianMaterial, MaterialE, MetalMaterial,
+    },
+    mesh::{Mesh, MeshError},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    BadIndex {
+        path: PathBuf,
+        line: usize,
+        index: i64,
+    },
+    UnknownMaterial {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+    Mesh {
+        path: PathBuf,
+        source: MeshError,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Mesh { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::BadIndex { path, line, index } => {
+                write!(
+                    f,
+                    "{}:{}: index {} out of range",
+                    path.display(),
+                    line,
+                    index
+                )
+            }
+            ObjError::UnknownMaterial { path, line, name } => {
+                write!(
+                    f,
+                    "{}:{}: unknown material '{}'",
+                    path.display(),
+                    line,
+                    name
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Mesh { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Loads a wavefront obj file and the mtl libraries it references. Every material used
+/// in the file becomes one `Mesh`, the meshes are returned wrapped in a bvh.
+pub fn load_obj(path: impl AsRef<Path>) -> Result<HittableE, ObjError> {
+    let path = path.as_ref();
+    parse_obj(&read(path)?, path)
+}
+
+/// Parses obj source, `path` is used for error messages and to resolve `mtllib` relative to it.
+pub fn parse_obj(src: &str, path: &Path) -> Result<HittableE, ObjError> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<Vec2> = vec![];
+
+    let mut materials: HashMap<String, MaterialE> = HashMap::new();
+    let mut groups: Vec<FaceGroup> = vec![FaceGroup::new(MaterialE::default())];
+    let mut current = 0;
+    // usemtl may switch back to a material used earlier in the file.
+    let mut group_by_name: HashMap<String, usize> = HashMap::new();
+
+    for (n, line) in src.lines().enumerate() {
+        let line_no = n + 1;
+        let mut it = line.split_whitespace();
+        let err = |message: &str| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_no,
+            message: message.to_string(),
+        };
+
+        match it.next() {
+            Some("v") => positions.push(parse_vec3(&mut it).ok_or_else(|| err("bad vertex"))?),
+            Some("vn") => normals.push(parse_vec3(&mut it).ok_or_else(|| err("bad normal"))?),
+            Some("vt") => {
+                let u = parse_f32(it.next()).ok_or_else(|| err("bad texture coordinate"))?;
+                // v is optional in the spec
+                let v = it.next().map_or(Some(0.0), |s| s.parse().ok());
+                let v = v.ok_or_else(|| err("bad texture coordinate"))?;
+                uvs.push(vec2(u, v));
+            }
+            Some("f") => {
+                let mut face = vec![];
+                for vert in it {
+                    face.push(parse_face_vertex(
+                        vert,
+                        (positions.len(), uvs.len(), normals.len()),
+                        path,
+                        line_no,
+                    )?);
+                }
+
+                if face.len() < 3 {
+                    return Err(err("face with fewer than 3 vertices"));
+                }
+
+                // fan triangulation, fine for the convex polygons exporters write.
+                for i in 1..face.len() - 1 {
+                    groups[current].push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            Some("mtllib") => {
+                let name = line.trim_start()["mtllib".len()..].trim();
+                if name.is_empty() {
+                    return Err(err("mtllib without a file name"));
+                }
+                let mtl_path = dir.join(name);
+                materials.extend(parse_mtl(&read(&mtl_path)?, &mtl_path)?);
+            }
+            Some("usemtl") => {
+                let name = line.trim_start()["usemtl".len()..].trim().to_string();
+                let material = materials
+                    .get(&name)
+                    .ok_or_else(|| ObjError::UnknownMaterial {
+                        path: path.to_path_buf(),
+                        line: line_no,
+                        name: name.clone(),
+                    })?
+                    .clone();
+
+                current = *group_by_name.entry(name).or_insert_with(|| {
+                    groups.push(FaceGroup::new(material));
+                    groups.len() - 1
+                });
+            }
+            // objects, groups, smoothing groups and comments don't affect the geometry.
+            _ => {}
+        }
+    }
+
+    let meshes = groups
+        .into_iter()
+        .filter(|g| !g.indices.is_empty())
+        .map(|g| {
+            g.into_mesh(&positions, &normals, &uvs)
+                .map(HittableE::Mesh)
+                .map_err(|source| ObjError::Mesh {
+                    path: path.to_path_buf(),
+                    source,
+                })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(HittableE::bvh(meshes))
+}
+
+// (position, uv, normal), 0 based, uv and normal are optional.
+type FaceVertex = (u32, Option<u32>, Option<u32>);
+
+struct FaceGroup {
+    material: MaterialE,
+    indices: Vec<[FaceVertex; 3]>,
+}
+
+impl FaceGroup {
+    fn new(material: MaterialE) -> Self {
+        Self {
+            material,
+            indices: vec![],
+        }
+    }
+
+    fn push(&mut self, tri: [FaceVertex; 3]) {
+        self.indices.push(tri);
+    }
+
+    // obj indexes each attribute separately, meshes share one index for all of them
+    // so every distinct combination becomes its own vertex.
+    fn into_mesh(
+        self,
+        positions: &[Vec3],
+        normals: &[Vec3],
+        uvs: &[Vec2],
+    ) -> Result<Mesh, MeshError> {
+        let has_uvs = self.indices.iter().flatten().all(|v| v.1.is_some());
+        let has_normals = self.indices.iter().flatten().all(|v| v.2.is_some());
+
+        let mut remap: HashMap<FaceVertex, u32> = HashMap::new();
+        let mut p = vec![];
+        let mut n = vec![];
+        let mut t = vec![];
+
+        let mut vertex = |v: FaceVertex| -> u32 {
+            let key = (v.0, v.1.filter(|_| has_uvs), v.2.filter(|_| has_normals));
+
+            *remap.entry(key).or_insert_with(|| {
+                p.push(positions[v.0 as usize]);
+                if let Some(i) = key.1 {
+                    t.push(uvs[i as usize]);
+                }
+                if let Some(i) = key.2 {
+                    n.push(normals[i as usize]);
+                }
+                p.len() as u32 - 1
+            })
+        };
+
+        let indices: Vec<UVec3> = self
+            .indices
+            .iter()
+            .map(|tri| uvec3(vertex(tri[0]), vertex(tri[1]), vertex(tri[2])))
+            .collect();
+
+        Mesh::new(p, n, t, indices, self.material)
+    }
+}
+
+// nan and inf parse as floats, but aren't valid coordinates or colors.
+fn parse_f32(s: Option<&str>) -> Option<f32> {
+    s?.parse().ok().filter(|x: &f32| x.is_finite())
+}
+
+fn parse_vec3<'a>(it: &mut impl Iterator<Item = &'a str>) -> Option<Vec3> {
+    Some(vec3(
+        parse_f32(it.next())?,
+        parse_f32(it.next())?,
+        parse_f32(it.next())?,
+    ))
+}
+
+// resolves a 1 based (or negative, relative to the end) obj index.
+fn resolve_index(s: &str, len: usize, path: &Path, line: usize) -> Result<u32, ObjError> {
+    let index: i64 = s.parse().map_err(|_| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message: format!("bad index '{}'", s),
+    })?;
+
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(ObjError::BadIndex {
+            path: path.to_path_buf(),
+            line,
+            index,
+        });
+    }
+
+    Ok(resolved as u32)
+}
+
+fn parse_face_vertex(
+    s: &str,
+    lens: (usize, usize, usize),
+    path: &Path,
+    line: usize,
+) -> Result<FaceVertex, ObjError> {
+    let mut parts = s.split('/');
+
+    let p = resolve_index(parts.next().unwrap_or(""), lens.0, path, line)?;
+    let t = match parts.next() {
+        Some("") | None => None,
+        Some(i) => Some(resolve_index(i, lens.1, path, line)?),
+    };
+    let n = match parts.next() {
+        Some("") | None => None,
+        Some(i) => Some(resolve_index(i, lens.2, path, line)?),
+    };
+
+    Ok((p, t, n))
+}
+
+#[derive(Default)]
+struct MtlEntry {
+    kd: Option<Vec3>,
+    ks: Option<Vec3>,
+    ke: Option<Vec3>,
+    tf: Option<Vec3>,
+    ns: Option<f32>,
+    ni: Option<f32>,
+    d: Option<f32>,
+}
+
+impl MtlEntry {
+    // mtl describes a phong-ish surface, pick whichever of our materials is closest.
+    fn to_material(&self) -> MaterialE {
+        let kd = self.kd.unwrap_or(Vec3::splat(0.8));
+        let ks = self.ks.unwrap_or(Vec3::ZERO);
+
+        if let Some(ke) = self.ke.filter(|ke| ke.max_element() > 0.0) {
+            return MaterialE::DiffuseLight(DiffuseLightMaterial::new(ke));
+        }
+
+        if self.d.is_some_and(|d| d < 1.0) {
+            let ni = self.ni.filter(|ni| *ni > 1.0).unwrap_or(1.5);
+            let tint = self.tf.unwrap_or(Vec3::ONE);
+            return MaterialE::Dialetric(DialetricMaterial::new(tint, ni));
+        }
+
+        if ks.max_element() > kd.max_element() {
+            // map the phong exponent onto a fuzz radius, sharp highlights are low fuzz.
+            let ns = self.ns.unwrap_or(0.0).max(0.0);
+            let fuzz = (2.0 / (ns + 2.0)).sqrt();
+            return MaterialE::Metal(MetalMaterial::new(ks, fuzz));
+        }
+
+        MaterialE::Lambertian(LambertianMaterial::new(kd))
+    }
+}
+
+/// Parses mtl source into our materials keyed by name.
+pub fn parse_mtl(src: &str, path: &Path) -> Result<HashMap<String, MaterialE>, ObjError> {
+    let mut entries: Vec<(String, MtlEntry)> = vec![];
+
+    for (n, line) in src.lines().enumerate() {
+        let line_no = n + 1;
+        let mut it = line.split_whitespace();
+        let key = it.next();
+
+        let err = |message: &str| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_no,
+            message: message.to_string(),
+        };
+
+        if key == Some("newmtl") {
+            let name = line.trim_start()["newmtl".len()..].trim();
+            if name.is_empty() {
+                return Err(err("newmtl without a name"));
+            }
+            entries.push((name.to_string(), MtlEntry::default()));
+            continue;
+        }
+
+        let Some(key) = key.filter(|k| !k.starts_with('#')) else {
+            continue;
+        };
+
+        let Some((_, entry)) = entries.last_mut() else {
+            return Err(err("material statement before newmtl"));
+        };
+
+        let color = |it: &mut std::str::SplitWhitespace| {
+            parse_vec3(it).ok_or_else(|| err(&format!("bad color for {}", key)))
+        };
+        let scalar = |it: &mut std::str::SplitWhitespace| {
+            parse_f32(it.next()).ok_or_else(|| err(&format!("bad value for {}", key)))
+        };
+
+        match key {
+            "Kd" => entry.kd = Some(color(&mut it)?),
+            "Ks" => entry.ks = Some(color(&mut it)?),
+            "Ke" => entry.ke = Some(color(&mut it)?),
+            "Tf" => entry.tf = Some(color(&mut it)?),
+            "Ns" => entry.ns = Some(scalar(&mut it)?),
+            "Ni" => entry.ni = Some(scalar(&mut it)?),
+            "d" => entry.d = Some(scalar(&mut it)?),
+            "Tr" => entry.d = Some(1.0 - scalar(&mut it)?),
+            // texture maps, illumination models etc. are not supported yet.
+            _ => {}
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|(name, e)| (name, e.to_material()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{Hitable, Interval};
+    use crate::ray::Ray;
+    use crate::texture::TextureE;
+
+    #[test]
+    fn test_parse_quad() {
+        let src = "
+# a unit quad
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+vn 0 0 1
+f 1//1 2//1 3//1 -4//1
+";
+        let world = parse_obj(src, Path::new("quad.obj")).unwrap();
+
+        let r = Ray::new(vec3(0.5, 0.5, 1.0), vec3(0.0, 0.0, -1.0));
+        let h = world.hit(&r, Interval::new(0.0, f32::INFINITY)).unwrap();
+        assert_eq!(h.t, 1.0);
+        assert_eq!(h.normal, vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_bad_index_reports_line() {
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 4\n";
+
+        match parse_obj(src, Path::new("bad.obj")) {
+            Err(ObjError::BadIndex { line, index, .. }) => {
+                assert_eq!(line, 5);
+                assert_eq!(index, 4);
+            }
+            _ => panic!("expected a bad index error"),
+        }
+    }
+
+    #[test]
+    fn test_non_finite_vertex_reports_line() {
+        let src = "v 0 0 0\nv 1 nan inf\n";
+
+        match parse_obj(src, Path::new("nan.obj")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn test_missing_mtllib() {
+        let src = "mtllib does_not_exist.mtl\n";
+
+        match parse_obj(src, Path::new("/nonexistent/scene.obj")) {
+            Err(ObjError::Io { path, .. }) => {
+                assert_eq!(path, Path::new("/nonexistent/does_not_exist.mtl"))
+            }
+            _ => panic!("expected an io error"),
+        }
+    }
+
+    #[test]
+    fn test_mtl_mapping() {
+        let src = "
+newmtl matte
+Kd 0.8 0.1 0.1
+
+newmtl chrome
+Kd 0.1 0.1 0.1
+Ks 0.9 0.9 0.9
+Ns 1000
+
+newmtl glass
+Ni 1.45
+d 0.1
+
+newmtl lamp
+Ke 4 4 4
+";
+        let m = parse_mtl(src, Path::new("test.mtl")).unwrap();
+
+        assert!(
+            matches!(&m["matte"], MaterialE::Lambertian(l) if l.albedo == vec3(0.8, 0.1, 0.1).into())
+        );
+        assert!(
+            matches!(&m["chrome"], MaterialE::Metal(l) if matches!(l.fuzz, TextureE::Constant(f) if f.x < 0.1))
+        );
+        assert!(
+            matches!(&m["glass"], MaterialE::Dialetric(l) if l.refractive_index == TextureE::from(1.45))
+        );
+        assert!(
+            matches!(&m["lamp"], MaterialE::DiffuseLight(l) if l.radiance == TextureE::Constant(Vec3::splat(4.0)))
+        );
+    }
+}
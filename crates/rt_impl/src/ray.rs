@@ -1,20 +1,37 @@
-use spirv_std::glam::{Vec2, Vec3};
+use spirv_std::glam::Vec3;
 
 #[derive(Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub t: f32,
-    pub seed: Vec2,
+    // the ray stands for a cone, `width` across at the origin and growing by `spread` per
+    // unit of distance. used to filter textures, 0 for both is a thin ray.
+    pub width: f32,
+    pub spread: f32,
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3, seed: Vec2) -> Self {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
         Self {
             origin,
             direction,
             t: 0.0,
-            seed,
+            width: 0.0,
+            spread: 0.0,
         }
     }
+
+    pub fn with_cone(self, width: f32, spread: f32) -> Self {
+        Self {
+            width,
+            spread,
+            ..self
+        }
+    }
+
+    /// width of the cone `distance` from the origin.
+    pub fn width_at(&self, distance: f32) -> f32 {
+        self.width + self.spread * distance
+    }
 }
@@ -0,0 +1,124 @@
+use spirv_std::glam::{vec2, vec3, UVec2, Vec2, Vec3};
+
+// 2^-24, turns the top 24 bits of a u32 into a float in [0, 1).
+pub(crate) const U32_TO_UNIT: f32 = 1.0 / 16_777_216.0;
+
+/// pcg hash from Jarzynski and Olano, "Hash Functions for GPU Rendering" (2020).
+pub fn pcg_hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Random numbers of one path. Every value is a hash of a key, made from the pixel, the
+/// sample index and the seed, and of the dimension, which counts the values taken so far.
+/// So a sample doesn't depend on the ones rendered before it and two dimensions of the
+/// same path don't share any bits. Only u32 ops, so it runs in shaders as well.
+#[derive(Copy, Clone, Debug)]
+pub struct Rng {
+    key: u32,
+    dimension: u32,
+}
+
+impl Rng {
+    pub fn new(pixel: UVec2, sample: u32, seed: u32) -> Self {
+        let key = pcg_hash(pixel.x ^ pcg_hash(pixel.y ^ pcg_hash(sample ^ pcg_hash(seed))));
+        Self { key, dimension: 0 }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let v = pcg_hash(self.key ^ pcg_hash(self.dimension));
+        self.dimension += 1;
+        v
+    }
+
+    /// uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * U32_TO_UNIT
+    }
+
+    pub fn next_vec2(&mut self) -> Vec2 {
+        let x = self.next_f32();
+        vec2(x, self.next_f32())
+    }
+
+    pub fn next_vec3(&mut self) -> Vec3 {
+        let x = self.next_f32();
+        let y = self.next_f32();
+        vec3(x, y, self.next_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spirv_std::glam::uvec2;
+
+    #[test]
+    fn test_uniform_and_reproducible() {
+        let n = 100_000;
+        let mut rng = Rng::new(uvec2(3, 7), 11, 0);
+        let values: Vec<f32> = (0..n).map(|_| rng.next_f32()).collect();
+
+        assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+
+        let mut buckets = [0u32; 10];
+        for v in values.iter() {
+            buckets[(v * 10.0) as usize] += 1;
+        }
+        for b in buckets {
+            assert!((b as f32 - n as f32 / 10.0).abs() < n as f32 * 0.005);
+        }
+
+        let mut again = Rng::new(uvec2(3, 7), 11, 0);
+        assert!(values.iter().all(|v| *v == again.next_f32()));
+    }
+
+    // neighbouring dimensions, pixels, samples and seeds must not be correlated.
+    #[test]
+    fn test_decorrelated() {
+        fn correlation(pairs: &[(f32, f32)]) -> f32 {
+            let n = pairs.len() as f32;
+            let (ma, mb) = pairs
+                .iter()
+                .fold((0.0, 0.0), |(a, b), (x, y)| (a + x / n, b + y / n));
+            let (mut cov, mut va, mut vb) = (0.0, 0.0, 0.0);
+            for (x, y) in pairs {
+                cov += (x - ma) * (y - mb);
+                va += (x - ma) * (x - ma);
+                vb += (y - mb) * (y - mb);
+            }
+            cov / (va * vb).sqrt()
+        }
+
+        let n = 20_000;
+        let dimensions: Vec<(f32, f32)> = (0..n)
+            .map(|i| {
+                let mut rng = Rng::new(uvec2(i % 100, i / 100), 0, 0);
+                (rng.next_f32(), rng.next_f32())
+            })
+            .collect();
+        let pixels: Vec<(f32, f32)> = (0..n)
+            .map(|i| {
+                let a = Rng::new(uvec2(i, 0), 0, 0).next_f32();
+                (a, Rng::new(uvec2(i + 1, 0), 0, 0).next_f32())
+            })
+            .collect();
+        let samples: Vec<(f32, f32)> = (0..n)
+            .map(|i| {
+                let a = Rng::new(uvec2(5, 5), i, 0).next_f32();
+                (a, Rng::new(uvec2(5, 5), i + 1, 0).next_f32())
+            })
+            .collect();
+        let seeds: Vec<(f32, f32)> = (0..n)
+            .map(|i| {
+                let a = Rng::new(uvec2(i, 1), 0, 0).next_f32();
+                (a, Rng::new(uvec2(i, 1), 0, 1).next_f32())
+            })
+            .collect();
+
+        for pairs in [dimensions, pixels, samples, seeds] {
+            assert!(correlation(&pairs).abs() < 0.03);
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use std::sync::OnceLock;
+
+use spirv_std::glam::{uvec2, vec2, vec3, UVec2, Vec2, Vec3};
+
+use crate::{
+    rng::{pcg_hash, Rng, U32_TO_UNIT},
+    ShaderConstants,
+};
+
+// values for `ShaderConstants::sampler`.
+// uncorrelated random numbers from `Rng`.
+pub const SAMPLER_INDEPENDENT: u32 = 0;
+// jittered strata, correlated multi-jittered in 2d, needs `aa_stages` to size them.
+pub const SAMPLER_STRATIFIED: u32 = 1;
+// halton sequence randomized per pixel with a random shift.
+pub const SAMPLER_HALTON: u32 = 2;
+// owen scrambled sobol, padded in groups of 4 dimensions.
+pub const SAMPLER_SOBOL: u32 = 3;
+// one sobol sequence for the whole image, shifted per pixel by a blue noise mask so the
+// remaining error is spread out like blue noise.
+pub const SAMPLER_BLUE_NOISE: u32 = 4;
+
+fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 * U32_TO_UNIT
+}
+
+/// Values for the dimensions of one sample of one pixel. Each call takes the next
+/// dimensions, the camera takes the pixel and lens ones first, then every bounce takes
+/// its bsdf and light ones. Dimensions the mode has no good points for come from `Rng`.
+#[derive(Copy, Clone, Debug)]
+pub struct Sampler {
+    mode: u32,
+    pixel: UVec2,
+    index: u32,
+    count: u32,
+    // hash of the pixel and seed, the same for every sample of the pixel.
+    key: u32,
+    seed: u32,
+    rng: Rng,
+    dimension: u32,
+}
+
+impl Sampler {
+    /// sampler for sample `index` of the `sc.aa_stages` ones of `pixel`.
+    pub fn new(sc: &ShaderConstants, pixel: UVec2, index: u32) -> Self {
+        Self {
+            mode: sc.sampler,
+            pixel,
+            index,
+            count: sc.aa_stages,
+            key: pcg_hash(pixel.x ^ pcg_hash(pixel.y ^ pcg_hash(sc.seed))),
+            seed: pcg_hash(sc.seed ^ 0x5bd1e995),
+            rng: Rng::new(pixel, index, sc.seed),
+            dimension: 0,
+        }
+    }
+
+    // hash of the pixel and dimension `d`.
+    fn dimension_key(&self, d: u32) -> u32 {
+        pcg_hash(self.key ^ pcg_hash(d))
+    }
+
+    /// uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        let d = self.dimension;
+        self.dimension += 1;
+
+        match self.mode {
+            SAMPLER_STRATIFIED if self.index < self.count => {
+                let p = self.dimension_key(d);
+                let stratum = permute(self.index, self.count, p);
+                (stratum as f32 + to_unit(pcg_hash(self.index ^ p))) / self.count as f32
+            }
+            SAMPLER_HALTON if (d as usize) < PRIMES.len() => {
+                let shift = to_unit(self.dimension_key(d));
+                (radical_inverse(PRIMES[d as usize], self.index) + shift).fract()
+            }
+            SAMPLER_SOBOL => {
+                // every 4 dimensions get their own shuffle of the sample order.
+                let group_key = self.dimension_key(d / 4);
+                let i = nested_uniform_scramble(self.index, group_key);
+                let x = sobol(i, d % 4);
+                to_unit(nested_uniform_scramble(x, pcg_hash(group_key ^ d)))
+            }
+            SAMPLER_BLUE_NOISE => {
+                // the same points for every pixel, so neighbours make errors of different
+                // signs once the blue noise shift is added.
+                let group_seed = pcg_hash(self.seed ^ pcg_hash(d / 4));
+                let i = nested_uniform_scramble(self.index, group_seed);
+                let x = to_unit(nested_uniform_scramble(
+                    sobol(i, d % 4),
+                    pcg_hash(group_seed ^ d),
+                ));
+                (x + blue_noise(self.pixel, d)).fract()
+            }
+            _ => self.rng.next_f32(),
+        }
+    }
+
+    pub fn next_vec2(&mut self) -> Vec2 {
+        if self.mode == SAMPLER_STRATIFIED && self.index < self.count {
+            let p = self.dimension_key(self.dimension);
+            self.dimension += 2;
+            return cmj(self.index, self.count, p);
+        }
+
+        let x = self.next_f32();
+        vec2(x, self.next_f32())
+    }
+
+    /// a 1d value and a 2d one, as the light sampling wants them.
+    pub fn next_vec3(&mut self) -> Vec3 {
+        let x = self.next_f32();
+        let yz = self.next_vec2();
+        vec3(x, yz.x, yz.y)
+    }
+}
+
+/// Kensler's hashed permutation of [0, l), "Correlated Multi-Jittered Sampling" (2013).
+pub fn permute(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+
+    (i.wrapping_add(p)) % l
+}
+
+// point `s` of `n` correlated multi-jittered points, stratified in 1d and 2d.
+fn cmj(s: u32, n: u32, p: u32) -> Vec2 {
+    let m = ((n as f32).sqrt() as u32).max(1);
+    let rows = n.div_ceil(m);
+
+    let s = permute(s, n, p.wrapping_mul(0x51633e2d));
+    let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+    let sy = permute(s / m, rows, p.wrapping_mul(0x02e5be93));
+    let jx = to_unit(pcg_hash(s ^ p.wrapping_mul(0x967a889b)));
+    let jy = to_unit(pcg_hash(s ^ p.wrapping_mul(0x368cc8b7)));
+
+    vec2(
+        ((s % m) as f32 + (sy as f32 + jx) / rows as f32) / m as f32,
+        ((s / m) as f32 + (sx as f32 + jy) / m as f32) / rows as f32,
+    )
+    .min(Vec2::splat(1.0 - f32::EPSILON))
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+fn radical_inverse(base: u32, i: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut i = i;
+    let mut f = inv_base;
+    let mut result = 0.0;
+    while i > 0 {
+        result += (i % base) as f32 * f;
+        i /= base;
+        f *= inv_base;
+    }
+    result.min(1.0 - f32::EPSILON)
+}
+
+// direction numbers of a sobol dimension from its primitive polynomial, given by its
+// degree `s`, the coefficients `a` and the initial numbers `m` (Joe and Kuo).
+const fn directions(s: usize, a: u32, m: [u32; 3]) -> [u32; 32] {
+    let mut v = [0u32; 32];
+    let mut i = 0;
+    while i < 32 {
+        if i < s {
+            v[i] = m[i] << (31 - i);
+        } else {
+            v[i] = v[i - s] ^ (v[i - s] >> s);
+            let mut k = 1;
+            while k < s {
+                v[i] ^= ((a >> (s - 1 - k)) & 1) * v[i - k];
+                k += 1;
+            }
+        }
+        i += 1;
+    }
+    v
+}
+
+const fn van_der_corput() -> [u32; 32] {
+    let mut v = [0u32; 32];
+    let mut i = 0;
+    while i < 32 {
+        v[i] = 1 << (31 - i);
+        i += 1;
+    }
+    v
+}
+
+const SOBOL_DIRECTIONS: [[u32; 32]; 4] = [
+    van_der_corput(),
+    directions(1, 0, [1, 0, 0]),
+    directions(2, 1, [1, 3, 0]),
+    directions(3, 1, [1, 3, 1]),
+];
+
+fn sobol(index: u32, dim: u32) -> u32 {
+    let v = &SOBOL_DIRECTIONS[dim as usize];
+    let mut x = 0;
+    let mut i = index;
+    let mut bit = 0;
+    while i > 0 {
+        if i & 1 == 1 {
+            x ^= v[bit];
+        }
+        i >>= 1;
+        bit += 1;
+    }
+    x
+}
+
+// Burley, "Practical Hash-based Owen Scrambling" (2020).
+fn laine_karras_permutation(x: u32, seed: u32) -> u32 {
+    let mut x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// side of the tiled blue noise mask.
+pub const BLUE_NOISE_SIZE: u32 = 64;
+
+/// Blue noise value in [0, 1) for `pixel`, moved by a different offset for every
+/// dimension so the dimensions don't share a mask.
+pub fn blue_noise(pixel: UVec2, dimension: u32) -> f32 {
+    // offsets along the r2 sequence spread well over the tile.
+    let shift = vec2(0.754_877_7, 0.569_840_3) * dimension as f32;
+    let offset = (shift.fract() * BLUE_NOISE_SIZE as f32).as_uvec2();
+    let p = (pixel + offset) % BLUE_NOISE_SIZE;
+
+    blue_noise_mask()[(p.y * BLUE_NOISE_SIZE + p.x) as usize]
+}
+
+/// Ulichney's void and cluster mask, every value in [0, 1) once. Built on first use.
+pub fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+fn void_and_cluster(size: u32, sigma: f32) -> Vec<f32> {
+    let n = (size * size) as usize;
+
+    // gaussian of the toroidal distance, indexed by the offset between two cells.
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let d = uvec2(i as u32 % size, i as u32 / size);
+            let d = d.min(UVec2::splat(size) - d).as_vec2();
+            (-d.length_squared() / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let offset = |a: usize, b: usize| {
+        let (ax, ay) = (a as u32 % size, a as u32 / size);
+        let (bx, by) = (b as u32 % size, b as u32 / size);
+        let dx = (ax + size - bx) % size;
+        let dy = (ay + size - by) % size;
+        (dy * size + dx) as usize
+    };
+
+    let mut energy = vec![0.0f32; n];
+    let mut on = vec![false; n];
+    let set = |energy: &mut [f32], on: &mut [bool], i: usize, value: bool| {
+        on[i] = value;
+        let sign = if value { 1.0 } else { -1.0 };
+        for (j, e) in energy.iter_mut().enumerate() {
+            *e += sign * kernel[offset(j, i)];
+        }
+    };
+    let tightest_cluster = |energy: &[f32], on: &[bool]| {
+        (0..n)
+            .filter(|i| on[*i])
+            .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+            .unwrap()
+    };
+    let largest_void = |energy: &[f32], on: &[bool]| {
+        (0..n)
+            .filter(|i| !on[*i])
+            .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+            .unwrap()
+    };
+
+    // a tenth of the cells at random, then moved from clusters into voids until stable.
+    let mut rng = Rng::new(UVec2::ZERO, 0, 0);
+    let initial = n / 10;
+    let mut count = 0;
+    while count < initial {
+        let i = (rng.next_u32() % n as u32) as usize;
+        if !on[i] {
+            set(&mut energy, &mut on, i, true);
+            count += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&energy, &on);
+        set(&mut energy, &mut on, cluster, false);
+        let void = largest_void(&energy, &on);
+        if void == cluster {
+            set(&mut energy, &mut on, cluster, true);
+            break;
+        }
+        set(&mut energy, &mut on, void, true);
+    }
+
+    let mut rank = vec![0u32; n];
+
+    // the initial points are ranked by taking the tightest clusters out first.
+    let (mut e1, mut on1) = (energy.clone(), on.clone());
+    for r in (0..initial).rev() {
+        let i = tightest_cluster(&e1, &on1);
+        set(&mut e1, &mut on1, i, false);
+        rank[i] = r as u32;
+    }
+
+    // the rest fill the largest voids in order.
+    for r in initial..n {
+        let i = largest_void(&energy, &on);
+        set(&mut energy, &mut on, i, true);
+        rank[i] = r as u32;
+    }
+
+    rank.iter().map(|r| *r as f32 / n as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [u32; 5] = [
+        SAMPLER_INDEPENDENT,
+        SAMPLER_STRATIFIED,
+        SAMPLER_HALTON,
+        SAMPLER_SOBOL,
+        SAMPLER_BLUE_NOISE,
+    ];
+
+    fn settings(sampler: u32, aa_stages: u32) -> ShaderConstants {
+        ShaderConstants {
+            sampler,
+            aa_stages,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_values_in_range_and_reproducible() {
+        for mode in MODES {
+            let sc = settings(mode, 16);
+            for i in 0..16 {
+                let mut a = Sampler::new(&sc, uvec2(3, 9), i);
+                let mut b = Sampler::new(&sc, uvec2(3, 9), i);
+                for _ in 0..64 {
+                    let (x, y) = (a.next_vec2(), b.next_vec2());
+                    assert!(x.cmpge(Vec2::ZERO).all() && x.cmplt(Vec2::ONE).all());
+                    assert_eq!(x, y);
+                }
+            }
+        }
+    }
+
+    // mean squared error of integrating a disk over many pixels, the low discrepancy
+    // samplers have to beat independent sampling by a good margin.
+    #[test]
+    fn test_converges_faster_than_independent() {
+        let spp = 64;
+        let exact = std::f32::consts::PI / 16.0;
+        let error = |mode: u32| -> f32 {
+            let sc = settings(mode, spp);
+            let pixels = 256;
+            let mut total = 0.0;
+            for p in 0..pixels {
+                let mut hits = 0;
+                for i in 0..spp {
+                    let mut s = Sampler::new(&sc, uvec2(p % 16, p / 16), i);
+                    // dimensions past the first pair, like the ones a bounce would use.
+                    s.next_vec2();
+                    let u = s.next_vec2();
+                    if u.distance(Vec2::splat(0.5)) < 0.25 {
+                        hits += 1;
+                    }
+                }
+                let e = hits as f32 / spp as f32 - exact;
+                total += e * e;
+            }
+            total / pixels as f32
+        };
+
+        let independent = error(SAMPLER_INDEPENDENT);
+        for mode in [
+            SAMPLER_STRATIFIED,
+            SAMPLER_HALTON,
+            SAMPLER_SOBOL,
+            SAMPLER_BLUE_NOISE,
+        ] {
+            let e = error(mode);
+            assert!(
+                e < independent * 0.5,
+                "sampler {mode}: {e} vs {independent}"
+            );
+        }
+    }
+
+    // blue noise has little low frequency energy, so averaging 2x2 blocks cancels much
+    // more of it than it does for white noise.
+    #[test]
+    fn test_blue_noise_mask() {
+        let mask = blue_noise_mask();
+        let n = BLUE_NOISE_SIZE as usize;
+        assert_eq!(mask.len(), n * n);
+
+        let mut sorted = mask.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        assert!(sorted.windows(2).all(|w| w[0] < w[1]));
+
+        // variance of the means of b x b blocks.
+        let block_variance = |values: &[f32], b: usize| -> f32 {
+            let mut total = 0.0;
+            for y in (0..n).step_by(b) {
+                for x in (0..n).step_by(b) {
+                    let mut sum = 0.0;
+                    for i in 0..b * b {
+                        sum += values[(y + i / b) * n + x + i % b];
+                    }
+                    total += (sum / (b * b) as f32 - 0.5).powi(2);
+                }
+            }
+            total / (n * n / (b * b)) as f32
+        };
+
+        let mut rng = Rng::new(UVec2::ZERO, 1, 0);
+        let white: Vec<f32> = (0..n * n).map(|_| rng.next_f32()).collect();
+        // low frequencies are missing, so the blocks average out much better than white
+        // noise does and the bigger the block, the bigger the gap.
+        let ratios: Vec<f32> = [2, 4, 8]
+            .iter()
+            .map(|b| block_variance(mask, *b) / block_variance(&white, *b))
+            .collect();
+        assert!(ratios[0] < 0.4, "{ratios:?}");
+        assert!(ratios.windows(2).all(|w| w[1] < w[0]), "{ratios:?}");
+    }
+}
@@ -0,0 +1,957 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use spirv_std::glam::{UVec2, UVec3, Vec2, Vec3};
+
+use crate::{
+    camera::Camera,
+    denoise::{DENOISE_ATROUS, DENOISE_NONE},
+    gltf::load_gltf,
+    hittable::{HittableE, Sphere},
+    material::{
+        DialetricMaterial, DiffuseLightMaterial, LambertianMaterial, MaterialE, MetalMaterial,
+    },
+    mesh::{Mesh, Triangle},
+    mipmap::{
+        MIP_BILINEAR, MIP_EWA, MIP_NEAREST, MIP_TRILINEAR, WRAP_CLAMP, WRAP_MIRROR, WRAP_REPEAT,
+    },
+    modes::{Display, Dof, Filter, Sampler, ToneMap, WorkingSpace},
+    obj::{load_obj, ObjError},
+    texture::{CheckerTexture, GradientTexture, ImageTexture, TextureE, CHECKER_SOLID, CHECKER_UV},
+    texture_file::{load_texture, TextureFileError},
+    Scene, ShaderConstants,
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    // `entry` names the offending table, e.g. `materials.gold` or `objects[3]`.
+    Invalid {
+        path: PathBuf,
+        entry: String,
+        message: String,
+    },
+    Obj(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+            SceneError::Invalid {
+                path,
+                entry,
+                message,
+            } => write!(f, "{}: {}: {}", path.display(), entry, message),
+            SceneError::Obj(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Obj(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(e: ObjError) -> Self {
+        SceneError::Obj(e)
+    }
+}
+
+// the file layout, kept apart from the renderer types so those don't need serde.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileScene {
+    camera: Option<FileCamera>,
+    #[serde(default)]
+    settings: FileSettings,
+    #[serde(default)]
+    textures: BTreeMap<String, FileTexture>,
+    #[serde(default)]
+    materials: BTreeMap<String, FileMaterial>,
+    #[serde(default)]
+    objects: Vec<FileObject>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileCamera {
+    position: [f32; 3],
+    look_at: [f32; 3],
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+    // degrees
+    vfov: f32,
+    aspect: Option<f32>,
+    #[serde(default)]
+    aperture: f32,
+    // defaults to the distance to `look_at`.
+    focus_distance: Option<f32>,
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileSettings {
+    width: Option<u32>,
+    height: Option<u32>,
+    samples: Option<u32>,
+    bounce_limit: Option<i32>,
+    focus_point: Option<f32>,
+    sky: Option<bool>,
+    dof: Option<Dof>,
+    seed: Option<u32>,
+    sampler: Option<Sampler>,
+    filter: Option<Filter>,
+    tonemap: Option<ToneMap>,
+    // stops
+    exposure: Option<f32>,
+    white_point: Option<f32>,
+    working_space: Option<WorkingSpace>,
+    display: Option<Display>,
+    denoise: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum FileTexture {
+    Checker {
+        even: [f32; 3],
+        odd: [f32; 3],
+        // size of a cell
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default)]
+        mapping: FileMapping,
+    },
+    Gradient {
+        from: [f32; 3],
+        to: [f32; 3],
+        start: [f32; 3],
+        end: [f32; 3],
+    },
+    // png, hdr or exr, path is relative to the scene file.
+    Image {
+        path: PathBuf,
+        // repeats over the uv square
+        #[serde(default = "default_tiling")]
+        scale: [f32; 2],
+        #[serde(default)]
+        wrap: FileWrap,
+        #[serde(default)]
+        filter: FileTextureFilter,
+        // png values are srgb unless this is set, hdr and exr are always linear.
+        #[serde(default)]
+        linear: bool,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum FileWrap {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum FileTextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+    #[default]
+    Ewa,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum FileMapping {
+    #[default]
+    Solid,
+    Uv,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_tiling() -> [f32; 2] {
+    [1.0, 1.0]
+}
+
+// either a color or the name of an entry in `textures`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileColor {
+    Rgb([f32; 3]),
+    Texture(String),
+}
+
+// either a number or the name of an entry in `textures`, whose mean channel is used.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileValue {
+    Number(f32),
+    Texture(String),
+}
+
+impl Default for FileValue {
+    fn default() -> Self {
+        FileValue::Number(0.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum FileMaterial {
+    Lambertian {
+        albedo: FileColor,
+    },
+    Metal {
+        albedo: FileColor,
+        #[serde(default)]
+        fuzz: FileValue,
+    },
+    Dielectric {
+        #[serde(default = "default_white")]
+        albedo: FileColor,
+        #[serde(default = "default_ior")]
+        ior: FileValue,
+    },
+    Light {
+        radiance: FileColor,
+    },
+}
+
+fn default_white() -> FileColor {
+    FileColor::Rgb([1.0, 1.0, 1.0])
+}
+
+fn default_ior() -> FileValue {
+    FileValue::Number(1.5)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum FileObject {
+    Sphere {
+        center: [f32; 3],
+        // negative radii turn the normals inwards, for hollow glass.
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        material: String,
+    },
+    Quad {
+        corner: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        material: String,
+    },
+    Mesh {
+        positions: Vec<[f32; 3]>,
+        #[serde(default)]
+        normals: Vec<[f32; 3]>,
+        #[serde(default)]
+        uvs: Vec<[f32; 2]>,
+        indices: Vec<[u32; 3]>,
+        material: String,
+    },
+    // path is relative to the scene file, materials come from its mtl files.
+    Obj {
+        path: PathBuf,
+    },
+    // .gltf or .glb, relative to the scene file. its first camera is used when the scene
+    // has no camera of its own.
+    Gltf {
+        path: PathBuf,
+    },
+}
+
+/// Loads a toml scene description, returns the scene and the render settings it asks for.
+/// Settings missing from the file keep their `ShaderConstants::default()` values.
+pub fn load_scene_file(path: impl AsRef<Path>) -> Result<(Scene, ShaderConstants), SceneError> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    parse_scene_file(&src, path)
+}
+
+/// Parses scene source, `path` is used for error messages and to resolve obj, gltf and
+/// image files.
+pub fn parse_scene_file(src: &str, path: &Path) -> Result<(Scene, ShaderConstants), SceneError> {
+    let file: FileScene = toml::from_str(src).map_err(|e| SceneError::Parse {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+
+    let invalid = |entry: String, message: &str| SceneError::Invalid {
+        path: path.to_path_buf(),
+        entry,
+        message: message.to_string(),
+    };
+
+    let settings = settings(&file.settings).map_err(|m| invalid("settings".into(), m))?;
+
+    let mut camera = match &file.camera {
+        Some(c) => Some(camera(c).map_err(|m| invalid("camera".into(), m))?),
+        None => None,
+    };
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut textures = BTreeMap::new();
+    for (name, t) in file.textures.iter() {
+        let entry = || format!("textures.{name}");
+        let t = match t {
+            FileTexture::Image {
+                path,
+                scale,
+                wrap,
+                filter,
+                linear,
+            } => {
+                if !finite(scale) || scale.contains(&0.0) {
+                    return Err(invalid(entry(), "scale must be finite and non-zero"));
+                }
+                let image = load_texture(dir.join(path), *linear).map_err(|e| match e {
+                    TextureFileError::Io { path, source } => SceneError::Io { path, source },
+                    e => invalid(entry(), &e.to_string()),
+                })?;
+
+                let wrap = UVec2::splat(match wrap {
+                    FileWrap::Repeat => WRAP_REPEAT,
+                    FileWrap::Clamp => WRAP_CLAMP,
+                    FileWrap::Mirror => WRAP_MIRROR,
+                });
+                let filter = match filter {
+                    FileTextureFilter::Nearest => MIP_NEAREST,
+                    FileTextureFilter::Bilinear => MIP_BILINEAR,
+                    FileTextureFilter::Trilinear => MIP_TRILINEAR,
+                    FileTextureFilter::Ewa => MIP_EWA,
+                };
+
+                TextureE::Image(ImageTexture::new(
+                    Arc::new(image),
+                    Vec2::from_array(*scale),
+                    wrap,
+                    filter,
+                ))
+            }
+            _ => texture(t).map_err(|msg| invalid(entry(), msg))?,
+        };
+        textures.insert(name.as_str(), t);
+    }
+
+    let mut materials = BTreeMap::new();
+    for (name, m) in file.materials.iter() {
+        let m = material(m, &textures).map_err(|msg| invalid(format!("materials.{name}"), &msg))?;
+        materials.insert(name.as_str(), m);
+    }
+
+    let mut objects = vec![];
+    for (i, o) in file.objects.iter().enumerate() {
+        let entry = || format!("objects[{i}]");
+
+        let lookup = |name: &String| {
+            materials
+                .get(name.as_str())
+                .cloned()
+                .ok_or_else(|| invalid(entry(), &format!("unknown material '{name}'")))
+        };
+
+        let obj = match o {
+            FileObject::Obj { path } => load_obj(dir.join(path))?,
+            FileObject::Gltf { path } => {
+                let gltf = load_gltf(dir.join(path))
+                    .map_err(|e| invalid(entry(), &format!("{}: {}", path.display(), e)))?;
+                if camera.is_none() {
+                    camera = gltf.cameras.first().map(|c| (*c).into());
+                }
+                gltf.world
+            }
+            FileObject::Sphere { material, .. }
+            | FileObject::Triangle { material, .. }
+            | FileObject::Quad { material, .. }
+            | FileObject::Mesh { material, .. } => {
+                let m = lookup(material)?;
+                object(o, m).map_err(|msg| invalid(entry(), &msg))?
+            }
+        };
+
+        objects.push(obj);
+    }
+
+    if objects.is_empty() {
+        return Err(invalid("objects".into(), "the scene is empty"));
+    }
+
+    let scene = Scene::new(HittableE::bvh(objects), camera.unwrap_or_default());
+    Ok((scene, settings))
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::from_array(v)
+}
+
+fn finite(v: &[f32]) -> bool {
+    v.iter().all(|x| x.is_finite())
+}
+
+fn settings(s: &FileSettings) -> Result<ShaderConstants, &'static str> {
+    let mut c = ShaderConstants::default();
+
+    c.width = s.width.unwrap_or(c.width);
+    c.height = s.height.unwrap_or(c.height);
+    c.aa_stages = s.samples.unwrap_or(c.aa_stages);
+    c.bounce_limit = s.bounce_limit.unwrap_or(c.bounce_limit);
+    c.focus_point = s.focus_point.unwrap_or(c.focus_point);
+    c.seed = s.seed.unwrap_or(c.seed);
+
+    if let Some(sky) = s.sky {
+        c.sky = sky.into();
+    }
+
+    if let Some(denoise) = s.denoise {
+        c.denoise = if denoise {
+            DENOISE_ATROUS
+        } else {
+            DENOISE_NONE
+        };
+    }
+
+    c.sampler = s.sampler.map_or(c.sampler, Sampler::mode);
+    c.filter = s.filter.map_or(c.filter, Filter::mode);
+    c.dof = s.dof.map_or(c.dof, Dof::mode);
+    c.tonemap = s.tonemap.map_or(c.tonemap, ToneMap::mode);
+    c.exposure = s.exposure.unwrap_or(c.exposure);
+    c.white_point = s.white_point.unwrap_or(c.white_point);
+    c.working_space = s.working_space.map_or(c.working_space, WorkingSpace::mode);
+    c.display = s.display.map_or(c.display, Display::mode);
+
+    if c.width == 0 || c.height == 0 {
+        return Err("width and height must be positive");
+    }
+    if c.aa_stages == 0 {
+        return Err("samples must be positive");
+    }
+    if c.bounce_limit < 0 {
+        return Err("bounce_limit can't be negative");
+    }
+    if !c.exposure.is_finite() {
+        return Err("exposure must be finite");
+    }
+    if !(c.white_point > 0.0 && c.white_point.is_finite()) {
+        return Err("white_point must be positive");
+    }
+    if !(c.focus_point > 0.0 && c.focus_point.is_finite()) {
+        return Err("focus_point must be positive");
+    }
+
+    Ok(c)
+}
+
+fn camera(c: &FileCamera) -> Result<Camera, &'static str> {
+    let all = [c.position, c.look_at, c.up].concat();
+    if !finite(&all) {
+        return Err("camera vectors must be finite");
+    }
+
+    let (position, look_at, up) = (vec3(c.position), vec3(c.look_at), vec3(c.up));
+    let forward = look_at - position;
+    if forward.length_squared() == 0.0 {
+        return Err("look_at is the same point as position");
+    }
+    if forward.cross(up).length_squared() == 0.0 {
+        return Err("up is parallel to the view direction");
+    }
+    if !(c.vfov > 0.0 && c.vfov < 180.0) {
+        return Err("vfov must be between 0 and 180 degrees");
+    }
+    if c.aspect.is_some_and(|a| !(a > 0.0 && a.is_finite())) {
+        return Err("aspect must be positive");
+    }
+    if !(c.aperture >= 0.0 && c.aperture.is_finite()) {
+        return Err("aperture can't be negative");
+    }
+    if c.focus_distance
+        .is_some_and(|d| !(d > 0.0 && d.is_finite()))
+    {
+        return Err("focus_distance must be positive");
+    }
+
+    let mut camera = Camera::new(position, look_at, up, c.vfov);
+    camera.aspect = c.aspect;
+    camera.aperture = c.aperture;
+    if let Some(d) = c.focus_distance {
+        camera.focus_distance = d;
+    }
+
+    Ok(camera)
+}
+
+fn non_negative(v: &[f32; 3]) -> bool {
+    finite(v) && v.iter().all(|x| *x >= 0.0)
+}
+
+// image textures are loaded by the caller.
+fn texture(t: &FileTexture) -> Result<TextureE, &'static str> {
+    match t {
+        FileTexture::Checker {
+            even,
+            odd,
+            scale,
+            mapping,
+        } => {
+            if !non_negative(even) || !non_negative(odd) {
+                return Err("colors can't be negative");
+            }
+            if !(*scale > 0.0 && scale.is_finite()) {
+                return Err("scale must be positive");
+            }
+            let mapping = match mapping {
+                FileMapping::Solid => CHECKER_SOLID,
+                FileMapping::Uv => CHECKER_UV,
+            };
+            Ok(TextureE::Checker(CheckerTexture::new(
+                vec3(*even),
+                vec3(*odd),
+                *scale,
+                mapping,
+            )))
+        }
+        FileTexture::Gradient {
+            from,
+            to,
+            start,
+            end,
+        } => {
+            if !non_negative(from) || !non_negative(to) {
+                return Err("colors can't be negative");
+            }
+            if !finite(start) || !finite(end) || start == end {
+                return Err("gradient needs two different, finite end points");
+            }
+            Ok(TextureE::Gradient(GradientTexture::new(
+                vec3(*from),
+                vec3(*to),
+                vec3(*start),
+                vec3(*end),
+            )))
+        }
+        FileTexture::Image { .. } => unreachable!("images are loaded by the caller"),
+    }
+}
+
+// `what` names the parameter in errors.
+fn color(
+    c: &FileColor,
+    what: &str,
+    textures: &BTreeMap<&str, TextureE>,
+) -> Result<TextureE, String> {
+    match c {
+        FileColor::Rgb(v) if non_negative(v) => Ok(vec3(*v).into()),
+        FileColor::Rgb(_) => Err(format!("{what} can't be negative")),
+        FileColor::Texture(name) => lookup_texture(name, textures),
+    }
+}
+
+fn lookup_texture(name: &str, textures: &BTreeMap<&str, TextureE>) -> Result<TextureE, String> {
+    textures
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("unknown texture '{name}'"))
+}
+
+fn material(m: &FileMaterial, textures: &BTreeMap<&str, TextureE>) -> Result<MaterialE, String> {
+    match m {
+        FileMaterial::Lambertian { albedo } => Ok(MaterialE::Lambertian(LambertianMaterial::new(
+            color(albedo, "albedo", textures)?,
+        ))),
+        FileMaterial::Metal { albedo, fuzz } => {
+            let albedo = color(albedo, "albedo", textures)?;
+            // textured fuzz is clamped where it's looked up.
+            let fuzz = match fuzz {
+                FileValue::Number(f) if (0.0..=1.0).contains(f) => (*f).into(),
+                FileValue::Number(_) => return Err("fuzz must be between 0 and 1".into()),
+                FileValue::Texture(name) => lookup_texture(name, textures)?,
+            };
+            Ok(MaterialE::Metal(MetalMaterial::new(albedo, fuzz)))
+        }
+        FileMaterial::Dielectric { albedo, ior } => {
+            let albedo = color(albedo, "albedo", textures)?;
+            // textured indices are kept positive where they're looked up.
+            let ior = match ior {
+                FileValue::Number(i) if *i > 0.0 && i.is_finite() => (*i).into(),
+                FileValue::Number(_) => return Err("ior must be positive".into()),
+                FileValue::Texture(name) => lookup_texture(name, textures)?,
+            };
+            Ok(MaterialE::Dialetric(DialetricMaterial::new(albedo, ior)))
+        }
+        FileMaterial::Light { radiance } => Ok(MaterialE::DiffuseLight(DiffuseLightMaterial::new(
+            color(radiance, "radiance", textures)?,
+        ))),
+    }
+}
+
+fn object(o: &FileObject, material: MaterialE) -> Result<HittableE, String> {
+    match o {
+        FileObject::Sphere { center, radius, .. } => {
+            if !finite(center) || !radius.is_finite() || *radius == 0.0 {
+                return Err("sphere needs a finite center and a non-zero radius".into());
+            }
+            Ok(HittableE::Sphere(Sphere::new(
+                vec3(*center),
+                *radius,
+                material,
+            )))
+        }
+        FileObject::Triangle { vertices, .. } => {
+            let [v0, v1, v2] = vertices.map(vec3);
+            if !finite(&vertices.concat()) || (v1 - v0).cross(v2 - v0).length_squared() == 0.0 {
+                return Err("triangle is degenerate".into());
+            }
+            Ok(HittableE::Triangle(Triangle::new(v0, v1, v2, material)))
+        }
+        FileObject::Quad { corner, u, v, .. } => {
+            let (q, u, v) = (vec3(*corner), vec3(*u), vec3(*v));
+            if !(q.is_finite() && u.is_finite() && v.is_finite())
+                || u.cross(v).length_squared() == 0.0
+            {
+                return Err("quad is degenerate".into());
+            }
+            Ok(HittableE::Mesh(Mesh::quad(q, u, v, material)))
+        }
+        FileObject::Mesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            ..
+        } => {
+            if indices.is_empty() {
+                return Err("mesh has no triangles".into());
+            }
+            if !finite(&positions.concat()) {
+                return Err("positions must be finite".into());
+            }
+            if !finite(&normals.concat()) || !finite(&uvs.concat()) {
+                return Err("normals and uvs must be finite".into());
+            }
+
+            let mesh = Mesh::new(
+                positions.iter().copied().map(vec3).collect(),
+                normals.iter().copied().map(vec3).collect(),
+                uvs.iter().copied().map(Vec2::from_array).collect(),
+                indices.iter().copied().map(UVec3::from_array).collect(),
+                material,
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(HittableE::Mesh(mesh))
+        }
+        FileObject::Obj { .. } | FileObject::Gltf { .. } => {
+            unreachable!("obj and gltf files are loaded by the caller")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Hitable, Interval},
+        light::LightE,
+        ray::Ray,
+        util::TempDir,
+    };
+
+    #[test]
+    fn test_parse_example_scene() {
+        let src = include_str!("../../../scenes/cornell.toml");
+        let (scene, sc) = parse_scene_file(src, Path::new("cornell.toml")).unwrap();
+
+        assert_eq!(sc.sky, 0);
+        assert_eq!((sc.width, sc.height), (800, 450));
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.camera.up, Vec3::Y);
+    }
+
+    #[test]
+    fn test_unknown_material_names_entry() {
+        let src = r#"
+            [materials.red]
+            type = "lambertian"
+            albedo = [1.0, 0.0, 0.0]
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "red"
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "blue"
+        "#;
+
+        match parse_scene_file(src, Path::new("s.toml")) {
+            Err(SceneError::Invalid { entry, message, .. }) => {
+                assert_eq!(entry, "objects[1]");
+                assert!(message.contains("blue"));
+            }
+            _ => panic!("expected an invalid entry"),
+        }
+    }
+
+    #[test]
+    fn test_textured_materials() {
+        let dir = TempDir::new("scene_file_textures");
+        let mut encoder = png::Encoder::new(fs::File::create(dir.join("label.png")).unwrap(), 2, 1);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[255, 0, 0, 0, 0, 255])
+            .unwrap();
+
+        let src = r#"
+            [textures.floor]
+            type = "checker"
+            even = [0.1, 0.1, 0.1]
+            odd = [0.9, 0.9, 0.9]
+            scale = 0.5
+
+            [textures.label]
+            type = "image"
+            path = "label.png"
+
+            [materials.floor]
+            type = "lambertian"
+            albedo = "floor"
+
+            [materials.label]
+            type = "metal"
+            albedo = "label"
+            fuzz = "floor"
+
+            [materials.lamp]
+            type = "light"
+            radiance = "floor"
+
+            [materials.glass]
+            type = "dielectric"
+            ior = "floor"
+
+            [[objects]]
+            type = "quad"
+            corner = [-1, 0, -1]
+            u = [2, 0, 0]
+            v = [0, 0, 2]
+            material = "floor"
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 1, 0]
+            radius = 0.5
+            material = "label"
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 3, 0]
+            radius = 0.2
+            material = "lamp"
+
+            [[objects]]
+            type = "sphere"
+            center = [3, 1, 0]
+            radius = 0.5
+            material = "glass"
+        "#;
+        let (scene, _) = parse_scene_file(src, &dir.join("s.toml")).unwrap();
+        let down = |x: f32| {
+            let r = Ray::new(vec3([x, 2.0, 0.0]), -Vec3::Y);
+            scene
+                .world
+                .hit(&r, Interval::new(0.0, f32::INFINITY))
+                .unwrap()
+        };
+
+        assert!(matches!(
+            &scene.lights[..],
+            [LightE::Sphere(l)] if matches!(l.radiance, TextureE::Checker(_))
+        ));
+
+        let MaterialE::Metal(label) = down(0.0).material else {
+            panic!("expected the metal label");
+        };
+        let TextureE::Image(image) = label.albedo else {
+            panic!("expected an image albedo");
+        };
+        let level = &image.image.levels[0];
+        assert_eq!(level.texels[0], vec3([1.0, 0.0, 0.0]));
+        assert_eq!(level.texels[1], vec3([0.0, 0.0, 1.0]));
+        // halved down to a single texel.
+        assert_eq!(image.image.levels.len(), 2);
+
+        assert!(matches!(
+            down(3.0).material,
+            MaterialE::Dialetric(g) if matches!(g.refractive_index, TextureE::Checker(_))
+        ));
+
+        let bad_filter = src.replace(
+            r#"path = "label.png""#,
+            r#"path = "label.png"
+            filter = "cubic""#,
+        );
+        assert!(matches!(
+            parse_scene_file(&bad_filter, &dir.join("s.toml")),
+            Err(SceneError::Parse { .. })
+        ));
+        let not_an_image = src.replace("label.png", "s.toml");
+        fs::write(dir.join("s.toml"), src).unwrap();
+        match parse_scene_file(&not_an_image, &dir.join("s.toml")) {
+            Err(SceneError::Invalid { entry, message, .. }) => {
+                assert_eq!(entry, "textures.label");
+                assert!(message.contains("png, hdr or exr"));
+            }
+            _ => panic!("expected an invalid entry"),
+        }
+
+        let missing_fuzz = src.replace(r#"fuzz = "floor""#, r#"fuzz = "wood""#);
+        match parse_scene_file(&missing_fuzz, &dir.join("s.toml")) {
+            Err(SceneError::Invalid { entry, message, .. }) => {
+                assert_eq!(entry, "materials.label");
+                assert!(message.contains("wood"));
+            }
+            _ => panic!("expected an invalid entry"),
+        }
+
+        let missing = src.replace(r#"albedo = "floor""#, r#"albedo = "wood""#);
+        match parse_scene_file(&missing, &dir.join("s.toml")) {
+            Err(SceneError::Invalid { entry, message, .. }) => {
+                assert_eq!(entry, "materials.floor");
+                assert!(message.contains("wood"));
+            }
+            _ => panic!("expected an invalid entry"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_material_and_mesh() {
+        let material = r#"
+            [materials.chrome]
+            type = "metal"
+            albedo = [0.9, 0.9, 0.9]
+            fuzz = 2.0
+        "#;
+
+        let mesh = r#"
+            [materials.white]
+            type = "lambertian"
+            albedo = [0.7, 0.7, 0.7]
+
+            [[objects]]
+            type = "mesh"
+            positions = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+            indices = [[0, 1, 3]]
+            material = "white"
+        "#;
+
+        let nan_normal = mesh.replace(
+            "indices = [[0, 1, 3]]",
+            "normals = [[0, 0, 1], [0, 0, 1], [0, nan, 1]]\n            indices = [[0, 1, 2]]",
+        );
+
+        let focus = format!("[settings]\nfocus_point = 0.0\n{}", mesh);
+
+        for (src, expected) in [
+            (focus.as_str(), "settings"),
+            (material, "materials.chrome"),
+            (mesh, "objects[0]"),
+            (&nan_normal, "objects[0]"),
+        ] {
+            match parse_scene_file(src, Path::new("s.toml")) {
+                Err(SceneError::Invalid { entry, .. }) => assert_eq!(entry, expected),
+                _ => panic!("expected an invalid entry"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_gltf_object_and_camera() {
+        let dir = TempDir::new("scene_file_gltf");
+        // one triangle at z = 0 seen by a camera at z = 3.
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0, 1] }],
+            "nodes": [{ "mesh": 0 }, { "camera": 0, "translation": [0.0, 0.0, 3.0] }],
+            "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.8, "znear": 0.1 } }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "buffers": [{ "byteLength": 36, "uri": "data:application/octet-stream;base64,AACAvwAAgL8AAAAAAACAPwAAgL8AAAAAAAAAAAAAgD8AAAAA" }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [-1.0, -1.0, 0.0], "max": [1.0, 1.0, 0.0] }]
+        }"#;
+        fs::write(dir.join("triangle.gltf"), gltf).unwrap();
+
+        let src = r#"
+            [[objects]]
+            type = "gltf"
+            path = "triangle.gltf"
+        "#;
+        let (scene, _) = parse_scene_file(src, &dir.join("s.toml")).unwrap();
+        assert_eq!(scene.camera.position, vec3([0.0, 0.0, 3.0]));
+
+        // a camera in the scene file wins.
+        let with_camera = format!(
+            "{src}
+            [camera]
+            position = [0, 0, 5]
+            look_at = [0, 0, 0]
+            vfov = 40
+            "
+        );
+        let (scene, _) = parse_scene_file(&with_camera, &dir.join("s.toml")).unwrap();
+        assert_eq!(scene.camera.position, vec3([0.0, 0.0, 5.0]));
+
+        let missing = src.replace("triangle.gltf", "missing.gltf");
+        match parse_scene_file(&missing, &dir.join("s.toml")) {
+            Err(SceneError::Invalid { entry, message, .. }) => {
+                assert_eq!(entry, "objects[0]");
+                assert!(message.contains("missing.gltf"));
+            }
+            _ => panic!("expected an invalid entry"),
+        }
+    }
+}
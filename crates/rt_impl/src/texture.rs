@@ -0,0 +1,370 @@
+use std::sync::Arc;
+
+use spirv_std::glam::{UVec2, Vec2, Vec3};
+
+use crate::{hittable::Hit, mipmap::MipMap, ray::Ray, util};
+
+// values for `CheckerTexture::mapping`.
+// cubes in world space, so the pattern carries on across objects and cut surfaces.
+pub const CHECKER_SOLID: u32 = 0;
+// squares in uv space, following the surface.
+pub const CHECKER_UV: u32 = 1;
+
+pub trait Texture {
+    /// color at `hit`.
+    fn value(&self, hit: &Hit) -> Vec3;
+}
+
+/// A color that can change over a surface, used for material parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureE {
+    Constant(Vec3),
+    Checker(CheckerTexture),
+    Gradient(GradientTexture),
+    Image(ImageTexture),
+}
+
+impl From<Vec3> for TextureE {
+    fn from(c: Vec3) -> Self {
+        TextureE::Constant(c)
+    }
+}
+
+// scalar parameters like `MetalMaterial::fuzz` are grey textures.
+impl From<f32> for TextureE {
+    fn from(v: f32) -> Self {
+        TextureE::Constant(Vec3::splat(v))
+    }
+}
+
+impl Texture for TextureE {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        match self {
+            TextureE::Constant(c) => *c,
+            TextureE::Checker(t) => t.value(hit),
+            TextureE::Gradient(t) => t.value(hit),
+            TextureE::Image(t) => t.value(hit),
+        }
+    }
+}
+
+impl TextureE {
+    /// mixes the parameters into `h`, for `MaterialE::id`.
+    pub fn hash(&self, h: u32) -> u32 {
+        let fold = |h: u32, v: &[f32]| v.iter().fold(h, |h, x| util::hash(h ^ x.to_bits()));
+
+        match self {
+            TextureE::Constant(c) => fold(h, &c.to_array()),
+            TextureE::Checker(t) => {
+                let h = fold(util::hash(h ^ 1) ^ t.mapping, &t.even.to_array());
+                fold(h, &[t.odd.x, t.odd.y, t.odd.z, t.scale])
+            }
+            TextureE::Gradient(t) => {
+                let h = fold(util::hash(h ^ 2), &t.from.to_array());
+                let h = fold(h, &t.to.to_array());
+                fold(fold(h, &t.start.to_array()), &t.end.to_array())
+            }
+            TextureE::Image(t) => {
+                // the image by its size and mean rather than its address, so ids are the
+                // same from run to run.
+                let (w, h_) = (t.image.width(), t.image.height());
+                let h = util::hash(util::hash(util::hash(h ^ 3) ^ w) ^ h_)
+                    ^ (t.wrap.x << 12 | t.wrap.y << 8 | t.filter);
+                let h = fold(h, &t.image.mean().to_array());
+                fold(h, &t.scale.to_array())
+            }
+        }
+    }
+}
+
+/// Alternates between two colors in cells `scale` wide.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CheckerTexture {
+    pub even: Vec3,
+    pub odd: Vec3,
+    pub scale: f32,
+    // one of `CHECKER_SOLID` or `CHECKER_UV`.
+    pub mapping: u32,
+}
+
+impl CheckerTexture {
+    pub fn new(even: Vec3, odd: Vec3, scale: f32, mapping: u32) -> Self {
+        Self {
+            even,
+            odd,
+            scale,
+            mapping,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let p = match self.mapping {
+            CHECKER_UV => hit.uv.extend(0.0),
+            _ => hit.position,
+        };
+
+        // nudged so a surface lying on a cell boundary, like a floor at y = 0, doesn't
+        // flicker between the cells on either side.
+        let cell = (p / self.scale + 1e-3).floor();
+        if ((cell.x + cell.y + cell.z) as i32 & 1) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+/// Blends from `from` at `start` to `to` at `end` along the line between them, constant
+/// past either end.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GradientTexture {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub start: Vec3,
+    pub end: Vec3,
+}
+
+impl GradientTexture {
+    pub fn new(from: Vec3, to: Vec3, start: Vec3, end: Vec3) -> Self {
+        Self {
+            from,
+            to,
+            start,
+            end,
+        }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let d = self.end - self.start;
+        if d.length_squared() == 0.0 {
+            return self.from;
+        }
+
+        let t = (hit.position - self.start).dot(d) / d.length_squared();
+        self.from.lerp(self.to, t.clamp(0.0, 1.0))
+    }
+}
+
+/// `image` repeated `scale` times over the unit uv square, filtered over `Hit::footprint`.
+/// Materials sharing an image share one copy of it.
+#[derive(Clone, Debug)]
+pub struct ImageTexture {
+    pub image: Arc<MipMap>,
+    pub scale: Vec2,
+    // `mipmap::WRAP_*` modes along u and v.
+    pub wrap: UVec2,
+    // one of the `mipmap::MIP_*` filters.
+    pub filter: u32,
+}
+
+impl ImageTexture {
+    pub fn new(image: Arc<MipMap>, scale: Vec2, wrap: UVec2, filter: u32) -> Self {
+        Self {
+            image,
+            scale,
+            wrap,
+            filter,
+        }
+    }
+}
+
+// the same image, not just the same texels.
+impl PartialEq for ImageTexture {
+    fn eq(&self, o: &Self) -> bool {
+        Arc::ptr_eq(&self.image, &o.image)
+            && self.scale == o.scale
+            && self.wrap == o.wrap
+            && self.filter == o.filter
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let footprint = &hit.footprint;
+        self.image.lookup(
+            self.filter,
+            hit.uv * self.scale,
+            footprint.axis0 * self.scale,
+            footprint.axis1 * self.scale,
+            self.wrap,
+        )
+    }
+}
+
+// the footprint is only stretched this far along the surface, past it grazing rays would
+// blur to the image's mean.
+const MAX_STRETCH: f32 = 64.0;
+
+/// The area of the surface a ray's cone covers at a hit, in uv as the axes of an ellipse
+/// around `Hit::uv`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Footprint {
+    pub axis0: Vec2,
+    pub axis1: Vec2,
+}
+
+impl Footprint {
+    /// Zero for rays without a cone or at surfaces without uv derivatives.
+    pub fn new(r: &Ray, hit: &Hit) -> Self {
+        let width = r.width_at(r.origin.distance(hit.position));
+        if width <= 0.0 {
+            return Footprint::default();
+        }
+
+        // the cone's circle seen on the surface, stretched along the ray's direction as
+        // the surface turns away from it.
+        let n = hit.normal;
+        let d = r.direction.normalize();
+        let across = n.cross(d);
+        let across = if across.length_squared() > 1e-8 {
+            across.normalize()
+        } else {
+            hit.frame.tangent
+        };
+        let along = across.cross(n);
+        let stretch = (1.0 / d.dot(n).abs()).min(MAX_STRETCH);
+
+        Self {
+            axis0: to_uv(hit, across * width),
+            axis1: to_uv(hit, along * width * stretch),
+        }
+    }
+}
+
+// the uv step that moves the hit by `v` along the surface, least squares through dpdu and
+// dpdv.
+fn to_uv(hit: &Hit, v: Vec3) -> Vec2 {
+    let (du, dv) = (hit.dpdu, hit.dpdv);
+    let (a, b, c) = (du.dot(du), du.dot(dv), dv.dot(dv));
+    let det = a * c - b * b;
+    if det.abs() < 1e-12 {
+        return Vec2::ZERO;
+    }
+
+    let (x, y) = (du.dot(v), dv.dot(v));
+    Vec2::new(c * x - b * y, a * y - b * x) / det
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{Frame, Hitable, HittableE, Interval, Sphere},
+        material::{LambertianMaterial, Material, MaterialE},
+        mipmap::{MipLevel, MIP_TRILINEAR, WRAP_CLAMP, WRAP_REPEAT},
+    };
+    use spirv_std::glam::{vec2, vec3};
+
+    fn hit_at(position: Vec3, uv: Vec2) -> Hit {
+        Hit {
+            position,
+            normal: Vec3::Y,
+            t: 1.0,
+            front_face: true,
+            material: MaterialE::default(),
+            barycentric: Vec2::ZERO,
+            uv,
+            dpdu: Vec3::X,
+            dpdv: Vec3::Z,
+            frame: Frame::new(Vec3::Y, Vec3::X),
+            object_id: 0,
+            footprint: Footprint::default(),
+        }
+    }
+
+    #[test]
+    fn test_checker() {
+        let (black, white) = (Vec3::ZERO, Vec3::ONE);
+        let solid = CheckerTexture::new(black, white, 0.5, CHECKER_SOLID);
+        let uv = CheckerTexture::new(black, white, 0.25, CHECKER_UV);
+
+        let at = |t: &CheckerTexture, p: Vec3, uv: Vec2| t.value(&hit_at(p, uv));
+        assert_eq!(at(&solid, vec3(0.1, 0.0, 0.1), Vec2::ZERO), black);
+        assert_eq!(at(&solid, vec3(0.6, 0.0, 0.1), Vec2::ZERO), white);
+        assert_eq!(at(&solid, vec3(-0.1, 0.0, 0.1), Vec2::ZERO), white);
+        // a floor on the boundary between two layers of cells picks one of them.
+        assert_eq!(
+            at(&solid, vec3(0.1, -1e-6, 0.1), Vec2::ZERO),
+            at(&solid, vec3(0.1, 1e-6, 0.1), Vec2::ZERO)
+        );
+
+        assert_eq!(at(&uv, Vec3::ZERO, vec2(0.1, 0.1)), black);
+        assert_eq!(at(&uv, Vec3::ZERO, vec2(0.3, 0.1)), white);
+        assert_eq!(at(&uv, Vec3::ZERO, vec2(0.3, 0.3)), black);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let t = GradientTexture::new(Vec3::ZERO, Vec3::ONE, Vec3::ZERO, vec3(0.0, 2.0, 0.0));
+        let at = |p: Vec3| t.value(&hit_at(p, Vec2::ZERO));
+
+        assert_eq!(at(vec3(5.0, -1.0, 0.0)), Vec3::ZERO);
+        assert_eq!(at(vec3(0.0, 1.0, 3.0)), Vec3::splat(0.5));
+        assert_eq!(at(vec3(0.0, 9.0, 0.0)), Vec3::ONE);
+    }
+
+    #[test]
+    fn test_image_texture_is_filtered_over_the_hit_footprint() {
+        // 2x2, red and green on the top row.
+        let image = Arc::new(MipMap::new(MipLevel::new(
+            2,
+            2,
+            vec![Vec3::X, Vec3::Y, Vec3::Z, Vec3::ONE],
+        )));
+        let wrap = UVec2::splat(WRAP_REPEAT);
+        assert_eq!(image.nearest(vec2(0.25, 0.75), wrap), Vec3::X);
+        assert_eq!(image.nearest(vec2(0.75, 0.25), wrap), Vec3::ONE);
+        // repeats.
+        assert_eq!(image.nearest(vec2(1.25, -0.25), wrap), Vec3::X);
+
+        // clamped so the pole doesn't blend in the wrapped around bottom row.
+        let texture = TextureE::Image(ImageTexture::new(
+            image.clone(),
+            Vec2::ONE,
+            UVec2::new(WRAP_REPEAT, WRAP_CLAMP),
+            MIP_TRILINEAR,
+        ));
+        let material = MaterialE::Lambertian(LambertianMaterial::new(texture));
+        let world = HittableE::List(vec![HittableE::Sphere(Sphere::new(
+            Vec3::ZERO,
+            1.0,
+            material,
+        ))]);
+
+        // straight down onto the top pole, which is the top row of the image.
+        let r = Ray::new(vec3(0.1, 5.0, 0.0), -Vec3::Y);
+        let t = Interval::new(0.0, f32::INFINITY);
+        let h = world.hit(&r, t).unwrap();
+        // a thin ray only blends the red and green texels.
+        let albedo = h.material.albedo(&h);
+        assert!(albedo.z.abs() < 1e-5 && (albedo.x + albedo.y - 1.0).abs() < 1e-5);
+
+        // a cone wider than the sphere only sees the whole image.
+        let h = world.hit(&r.with_cone(0.0, 10.0), t).unwrap();
+        assert!(h.material.albedo(&h).distance(image.mean()) < 1e-5);
+    }
+
+    #[test]
+    fn test_footprint_stretches_at_grazing_angles() {
+        // the floor of `hit_at` has u along x and v along z.
+        let h = hit_at(vec3(0.0, 0.0, 1.0), Vec2::ZERO);
+
+        let r = Ray::new(vec3(0.0, 1.0, 1.0), -Vec3::Y);
+        assert_eq!(Footprint::new(&r, &h), Footprint::default());
+
+        let f = Footprint::new(&r.with_cone(0.0, 0.01), &h);
+        assert!((f.axis0.length() - 0.01).abs() < 1e-6);
+        assert!((f.axis1.length() - 0.01).abs() < 1e-6);
+
+        // 45 degrees, twice as far along z.
+        let r = Ray::new(vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 1.0)).with_cone(0.0, 0.01);
+        let f = Footprint::new(&r, &h);
+        let width = 0.01 * 2f32.sqrt();
+        assert!((f.axis0.abs() - vec2(width, 0.0)).length() < 1e-6);
+        assert!((f.axis1.abs() - vec2(0.0, width * 2f32.sqrt())).length() < 1e-6);
+    }
+}
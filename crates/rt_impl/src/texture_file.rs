@@ -0,0 +1,606 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use half::f16;
+use spirv_std::glam::{vec3, Vec3};
+
+use crate::{
+    color::srgb_eotf,
+    mipmap::{MipLevel, MipMap},
+};
+
+#[derive(Debug)]
+pub enum TextureFileError {
+    Io { path: PathBuf, source: io::Error },
+    // the file is damaged, of an unknown type or uses something the readers don't support.
+    Decode { path: PathBuf, message: String },
+}
+
+impl fmt::Display for TextureFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureFileError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            TextureFileError::Decode { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextureFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TextureFileError::Io { source, .. } => Some(source),
+            TextureFileError::Decode { .. } => None,
+        }
+    }
+}
+
+// largest image the decoders accept, 16k by 8k. corrupt sizes are rejected before anything
+// is allocated for them, and the texel count stays well inside a u32.
+const MAX_TEXELS: usize = 1 << 27;
+
+fn check_size(width: usize, height: usize) -> Result<(), String> {
+    match width.checked_mul(height) {
+        Some(n) if n <= MAX_TEXELS => Ok(()),
+        _ => Err(format!("{width}x{height} is too large")),
+    }
+}
+
+/// Loads a png, Radiance hdr or OpenEXR image by its extension and builds its mipmap.
+/// png values are decoded from srgb unless `linear` is set, for data like roughness maps;
+/// hdr and exr are always linear.
+pub fn load_texture(path: impl AsRef<Path>, linear: bool) -> Result<MipMap, TextureFileError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|source| TextureFileError::Io {
+        path: path.into(),
+        source,
+    })?;
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let image = match extension.as_str() {
+        "png" => decode_png(&bytes, linear),
+        "hdr" => decode_hdr(&bytes),
+        "exr" => decode_exr(&bytes),
+        _ => Err("unknown image type, expected png, hdr or exr".into()),
+    };
+
+    image
+        .map(MipMap::new)
+        .map_err(|message| TextureFileError::Decode {
+            path: path.into(),
+            message,
+        })
+}
+
+/// 8 or 16 bit png of any color type. Alpha is dropped.
+pub fn decode_png(bytes: &[u8], linear: bool) -> Result<MipLevel, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    // palettes to rgb and low bit depths to 8 bits.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let (width, height) = reader.info().size();
+    check_size(width as usize, height as usize)?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let buf = &buf[..info.buffer_size()];
+
+    let values: Vec<f32> = match info.bit_depth {
+        png::BitDepth::Sixteen => buf
+            .chunks_exact(2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]) as f32 / 65535.0)
+            .collect(),
+        _ => buf.iter().map(|v| *v as f32 / 255.0).collect(),
+    };
+
+    let decode = |v: f32| if linear { v } else { srgb_eotf(v) };
+    let texels = values
+        .chunks_exact(info.color_type.samples())
+        .map(|p| match p.len() {
+            1 | 2 => Vec3::splat(decode(p[0])),
+            _ => vec3(decode(p[0]), decode(p[1]), decode(p[2])),
+        })
+        .collect();
+
+    Ok(MipLevel::new(info.width, info.height, texels))
+}
+
+/// Radiance rgbe image, flat or run length encoded, stored top to bottom.
+pub fn decode_hdr(bytes: &[u8]) -> Result<MipLevel, String> {
+    let mut rest = bytes;
+    let mut line = || -> Result<&str, String> {
+        let end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or("truncated header")?;
+        let l = std::str::from_utf8(&rest[..end]).map_err(|_| "bad header")?;
+        rest = &rest[end + 1..];
+        Ok(l)
+    };
+
+    let magic = line()?;
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err("not a radiance hdr file".into());
+    }
+    loop {
+        let l = line()?;
+        if l.is_empty() {
+            break;
+        }
+        if let Some(format) = l.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported format {format}"));
+            }
+        }
+    }
+
+    // other orientations are allowed by the format but nothing writes them.
+    let resolution: Vec<&str> = line()?.split_whitespace().collect();
+    let (height, width) = match resolution[..] {
+        ["-Y", h, "+X", w] => (h.parse::<u32>(), w.parse::<u32>()),
+        _ => return Err("unsupported image orientation".into()),
+    };
+    let (height, width) = match (height, width) {
+        (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+        _ => return Err("bad resolution".into()),
+    };
+
+    let data = rest;
+    let mut pos = 0usize;
+    let mut next = |n: usize| -> Result<&[u8], String> {
+        let bytes = pos
+            .checked_add(n)
+            .and_then(|end| data.get(pos..end))
+            .ok_or("truncated pixel data")?;
+        pos += n;
+        Ok(bytes)
+    };
+
+    let w = width as usize;
+    check_size(w, height as usize)?;
+    let mut texels = Vec::with_capacity(w * height as usize);
+    let mut scanline = vec![0u8; 4 * w];
+    for _ in 0..height {
+        // run length encoded scanlines start with 2, 2 and their width.
+        let start = next(4)?;
+        let rle = (8..0x8000).contains(&w) && start[0] == 2 && start[1] == 2;
+        if rle {
+            if ((start[2] as usize) << 8 | start[3] as usize) != w {
+                return Err("scanline width doesn't match the image".into());
+            }
+
+            // each component is run length encoded on its own.
+            for c in 0..4 {
+                let mut x = 0;
+                while x < w {
+                    let count = next(1)?[0] as usize;
+                    let (run, literal) = if count > 128 {
+                        (count - 128, false)
+                    } else {
+                        (count, true)
+                    };
+                    if run == 0 || x + run > w {
+                        return Err("bad run length".into());
+                    }
+
+                    if literal {
+                        for (i, v) in next(run)?.iter().enumerate() {
+                            scanline[4 * (x + i) + c] = *v;
+                        }
+                    } else {
+                        let v = next(1)?[0];
+                        for i in 0..run {
+                            scanline[4 * (x + i) + c] = v;
+                        }
+                    }
+                    x += run;
+                }
+            }
+        } else {
+            scanline[..4].copy_from_slice(start);
+            scanline[4..].copy_from_slice(next(4 * (w - 1))?);
+        }
+
+        texels.extend(scanline.chunks_exact(4).map(|p| {
+            if p[3] == 0 {
+                return Vec3::ZERO;
+            }
+            let scale = 2f32.powi(p[3] as i32 - 136);
+            vec3(p[0] as f32 + 0.5, p[1] as f32 + 0.5, p[2] as f32 + 0.5) * scale
+        }));
+    }
+
+    Ok(MipLevel::new(width, height, texels))
+}
+
+// compression ids of the exr header.
+const EXR_NONE: u8 = 0;
+const EXR_RLE: u8 = 1;
+const EXR_ZIPS: u8 = 2;
+const EXR_ZIP: u8 = 3;
+
+struct ExrChannel {
+    name: String,
+    // 0 uint, 1 half, 2 float.
+    pixel_type: i32,
+}
+
+impl ExrChannel {
+    fn size(&self) -> usize {
+        if self.pixel_type == 1 {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn value(&self, b: &[u8]) -> f32 {
+        match self.pixel_type {
+            0 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            1 => f16::from_le_bytes([b[0], b[1]]).to_f32(),
+            _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+}
+
+/// Single part scanline OpenEXR image, uncompressed or rle, zips or zip compressed. Uses
+/// the R, G and B channels, or Y for greyscale images.
+pub fn decode_exr(bytes: &[u8]) -> Result<MipLevel, String> {
+    let truncated = || "truncated file".to_string();
+    let slice = |at: usize, n: usize| {
+        at.checked_add(n)
+            .and_then(|end| bytes.get(at..end))
+            .ok_or_else(truncated)
+    };
+    let i32_at = |at: usize| slice(at, 4).map(|b| i32::from_le_bytes(b.try_into().unwrap()));
+    let cstr = |at: usize| -> Result<&str, String> {
+        let end = bytes[at.min(bytes.len())..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(truncated)?;
+        std::str::from_utf8(&bytes[at..at + end]).map_err(|_| "bad header".to_string())
+    };
+
+    if slice(0, 4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return Err("not an openexr file".into());
+    }
+    let version = i32_at(4)?;
+    if version & 0xff != 2 {
+        return Err("unsupported openexr version".into());
+    }
+    // tiled, long names are fine, deep and multipart.
+    if version & 0x200 != 0 || version & 0x1800 != 0 {
+        return Err("only scanline images with a single part are supported".into());
+    }
+
+    let mut channels = vec![];
+    let mut compression = None;
+    let mut window = None;
+
+    let mut at = 8;
+    loop {
+        let name = cstr(at)?;
+        if name.is_empty() {
+            at += 1;
+            break;
+        }
+        let kind = cstr(at + name.len() + 1)?;
+        at += name.len() + kind.len() + 2;
+        let size = usize::try_from(i32_at(at)?).map_err(|_| "negative attribute size")?;
+        let value = slice(at + 4, size)?;
+        at += 4 + size;
+
+        match name {
+            "channels" => {
+                let mut c = 0;
+                while value.get(c).is_some_and(|b| *b != 0) {
+                    let end = c + value[c..]
+                        .iter()
+                        .position(|b| *b == 0)
+                        .ok_or_else(truncated)?;
+                    let name = String::from_utf8_lossy(&value[c..end]).into_owned();
+                    let field = |i: usize| {
+                        value
+                            .get(end + 1 + 4 * i..end + 5 + 4 * i)
+                            .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+                            .ok_or_else(truncated)
+                    };
+                    let pixel_type = field(0)?;
+                    if !(0..=2).contains(&pixel_type) {
+                        return Err(format!("bad type for channel {name}"));
+                    }
+                    // field 1 holds pLinear and the reserved bytes.
+                    if field(2)? != 1 || field(3)? != 1 {
+                        return Err("subsampled channels are not supported".into());
+                    }
+                    channels.push(ExrChannel { name, pixel_type });
+                    c = end + 17;
+                }
+            }
+            "compression" => compression = value.first().copied(),
+            "dataWindow" if size == 16 => {
+                let v = |i: usize| i32::from_le_bytes(value[4 * i..4 * i + 4].try_into().unwrap());
+                window = Some((v(0), v(1), v(2), v(3)));
+            }
+            _ => {}
+        }
+    }
+
+    let (x0, y0, x1, y1) = window.ok_or("missing data window")?;
+    if x1 < x0 || y1 < y0 {
+        return Err("empty data window".into());
+    }
+    // widened, the corners can be anywhere in the i32 range.
+    let width = (i64::from(x1) - i64::from(x0) + 1) as usize;
+    let height = (i64::from(y1) - i64::from(y0) + 1) as usize;
+    check_size(width, height)?;
+
+    let lines_per_chunk = match compression {
+        Some(EXR_NONE | EXR_RLE | EXR_ZIPS) => 1,
+        Some(EXR_ZIP) => 16,
+        Some(c) => return Err(format!("unsupported compression {c}")),
+        None => return Err("missing compression".into()),
+    };
+    let compression = compression.unwrap_or(EXR_NONE);
+
+    let find = |n: &str| channels.iter().position(|c| c.name == n);
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y; 3],
+        _ => return Err("no R, G and B or Y channels".into()),
+    };
+
+    // where each channel starts in a line.
+    let mut starts = vec![];
+    let mut line_size = 0;
+    for c in channels.iter() {
+        starts.push(line_size);
+        line_size += width * c.size();
+    }
+
+    let mut texels = vec![Vec3::ZERO; width * height];
+    let chunks = height.div_ceil(lines_per_chunk);
+    for chunk in 0..chunks {
+        let offset = slice(at + 8 * chunk, 8)?;
+        let offset = usize::try_from(u64::from_le_bytes(offset.try_into().unwrap()))
+            .map_err(|_| truncated())?;
+
+        // each read that succeeds leaves the next offset inside the file, so the sums are safe.
+        let first = i64::from(i32_at(offset)?) - i64::from(y0);
+        let size = usize::try_from(i32_at(offset + 4)?).map_err(|_| "negative chunk size")?;
+        let data = slice(offset + 8, size)?;
+        if first < 0 || first as usize >= height {
+            return Err("chunk outside the data window".into());
+        }
+
+        let first = first as usize;
+        let lines = lines_per_chunk.min(height - first);
+        let expected = lines * line_size;
+        // chunks that don't get smaller are stored as they are.
+        let data = if size == expected || compression == EXR_NONE {
+            data.to_vec()
+        } else {
+            decompress_exr(compression, data, expected)?
+        };
+        if data.len() != expected {
+            return Err("chunk size doesn't match the image".into());
+        }
+
+        for l in 0..lines {
+            let line = &data[l * line_size..(l + 1) * line_size];
+            for x in 0..width {
+                let value = |i: usize| {
+                    let c = &channels[rgb[i]];
+                    c.value(&line[starts[rgb[i]] + x * c.size()..])
+                };
+                texels[(first + l) * width + x] = vec3(value(0), value(1), value(2));
+            }
+        }
+    }
+
+    Ok(MipLevel::new(width as u32, height as u32, texels))
+}
+
+// undoes rle or zip compression and the predictor and byte interleaving both apply on top.
+fn decompress_exr(compression: u8, data: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    let mut raw = Vec::with_capacity(expected);
+    if compression == EXR_RLE {
+        let mut i = 0;
+        while i < data.len() {
+            let count = data[i] as i8;
+            if count < 0 {
+                let n = -(count as i32) as usize;
+                raw.extend_from_slice(data.get(i + 1..i + 1 + n).ok_or("bad rle data")?);
+                i += 1 + n;
+            } else {
+                let v = *data.get(i + 1).ok_or("bad rle data")?;
+                raw.extend(std::iter::repeat_n(v, count as usize + 1));
+                i += 2;
+            }
+        }
+    } else {
+        // one byte more than expected is enough to tell the chunk is wrong.
+        flate2::read::ZlibDecoder::new(data)
+            .take(expected as u64 + 1)
+            .read_to_end(&mut raw)
+            .map_err(|e| format!("bad zip data: {e}"))?;
+    }
+
+    if raw.len() != expected {
+        return Err("chunk size doesn't match the image".into());
+    }
+
+    for i in 1..raw.len() {
+        raw[i] = raw[i - 1].wrapping_add(raw[i]).wrapping_sub(128);
+    }
+
+    // the first half holds the even bytes and the second the odd ones.
+    let half = raw.len().div_ceil(2);
+    let mut out = Vec::with_capacity(raw.len());
+    for i in 0..half {
+        out.push(raw[i]);
+        if half + i < raw.len() {
+            out.push(raw[half + i]);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // rgbe for values that are exact in it.
+    fn rgbe(c: [u8; 3], e: u8) -> Vec3 {
+        vec3(c[0] as f32 + 0.5, c[1] as f32 + 0.5, c[2] as f32 + 0.5) * 2f32.powi(e as i32 - 136)
+    }
+
+    #[test]
+    fn test_hdr_flat_and_rle() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n";
+        let mut file = header.to_vec();
+
+        // first scanline run length encoded, every component a run of 8 apart from
+        // red, which is 8 literals.
+        file.extend_from_slice(&[2, 2, 0, 8]);
+        file.push(8);
+        file.extend(0..8u8);
+        file.extend_from_slice(&[128 + 8, 20, 128 + 8, 30, 128 + 8, 129]);
+        // second flat.
+        for x in 0..8u8 {
+            file.extend_from_slice(&[x, 0, 0, if x == 0 { 0 } else { 130 }]);
+        }
+
+        let image = decode_hdr(&file).unwrap();
+        assert_eq!((image.width, image.height), (8, 2));
+        assert_eq!(image.texels[3], rgbe([3, 20, 30], 129));
+        assert_eq!(image.texels[8], Vec3::ZERO);
+        assert_eq!(image.texels[13], rgbe([5, 0, 0], 130));
+
+        assert!(decode_hdr(&file[..file.len() - 1]).is_err());
+        assert!(decode_hdr(b"#?RADIANCE\n\n+Y 2 +X 8\n").is_err());
+    }
+
+    // zip compressed 2x1 exr with half R, G and B channels.
+    fn zip_exr(rgb: [[f32; 2]; 3]) -> Vec<u8> {
+        let attribute = |out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+            out.extend_from_slice(kind.as_bytes());
+            out.push(0);
+            out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            out.extend_from_slice(value);
+        };
+
+        let mut chlist = vec![];
+        for name in ["B", "G", "R"] {
+            chlist.extend_from_slice(&[name.as_bytes()[0], 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+            chlist.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        chlist.push(0);
+
+        let window: Vec<u8> = [0i32, 0, 1, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let mut file = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        attribute(&mut file, "channels", "chlist", &chlist);
+        attribute(&mut file, "compression", "compression", &[EXR_ZIP]);
+        attribute(&mut file, "dataWindow", "box2i", &window);
+        file.push(0);
+
+        // the line, interleaved and run through the predictor, then deflated.
+        let line: Vec<u8> = [2, 1, 0]
+            .iter()
+            .flat_map(|c| rgb[*c].iter().flat_map(|v| f16::from_f32(*v).to_le_bytes()))
+            .collect();
+        let mut raw: Vec<u8> = line.iter().step_by(2).copied().collect();
+        raw.extend(line.iter().skip(1).step_by(2));
+        for i in (1..raw.len()).rev() {
+            raw[i] = raw[i].wrapping_sub(raw[i - 1]).wrapping_add(128);
+        }
+        let mut z = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        z.write_all(&raw).unwrap();
+        let data = z.finish().unwrap();
+
+        let offset = file.len() + 8;
+        file.extend_from_slice(&(offset as u64).to_le_bytes());
+        file.extend_from_slice(&0i32.to_le_bytes());
+        file.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        file.extend_from_slice(&data);
+        file
+    }
+
+    #[test]
+    fn test_zip_exr() {
+        let image = decode_exr(&zip_exr([[0.5, 2.0], [0.25, 0.0], [1.0, 100.0]])).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.texels, [vec3(0.5, 0.25, 1.0), vec3(2.0, 0.0, 100.0)]);
+
+        let mut damaged = zip_exr([[0.0; 2]; 3]);
+        let n = damaged.len();
+        damaged[n - 3] ^= 0xff;
+        assert!(decode_exr(&damaged).is_err());
+        assert!(decode_exr(&damaged[..n - 1]).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_sizes_are_errors() {
+        let file = zip_exr([[0.0; 2]; 3]);
+        // the data window is the last attribute, the chunk table follows the header.
+        let name = b"dataWindow\0box2i\0";
+        let size_at = file.windows(name.len()).position(|w| w == name).unwrap() + name.len();
+        let window_at = size_at + 4;
+        let table_at = window_at + 17;
+        let chunk_at = table_at + 8;
+
+        let corrupt = |at: usize, value: &[u8]| {
+            let mut f = file.clone();
+            f[at..at + value.len()].copy_from_slice(value);
+            decode_exr(&f)
+        };
+        let window = |v: [i32; 4]| -> Vec<u8> { v.iter().flat_map(|v| v.to_le_bytes()).collect() };
+
+        assert!(corrupt(size_at, &(-1i32).to_le_bytes()).is_err());
+        assert!(corrupt(window_at, &window([i32::MIN, 0, i32::MAX, 0])).is_err());
+        assert!(corrupt(window_at, &window([0, 0, 100_000, 100_000])).is_err());
+        assert!(corrupt(window_at, &window([0, i32::MIN, 1, i32::MAX])).is_err());
+        assert!(corrupt(table_at, &(u64::MAX - 4).to_le_bytes()).is_err());
+        assert!(corrupt(table_at, &(usize::MAX as u64).to_le_bytes()).is_err());
+        assert!(corrupt(chunk_at, &i32::MIN.to_le_bytes()).is_err());
+        assert!(corrupt(chunk_at + 4, &(-8i32).to_le_bytes()).is_err());
+        assert!(corrupt(chunk_at + 4, &i32::MAX.to_le_bytes()).is_err());
+
+        let hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 100000 +X 100000\n";
+        assert_eq!(decode_hdr(hdr).unwrap_err(), "100000x100000 is too large");
+        let hdr = b"#?RADIANCE\n\n-Y 4000000000 +X 4000000000\n";
+        assert!(decode_hdr(hdr).is_err());
+    }
+
+    #[test]
+    fn test_png_decoding() {
+        let mut file = vec![];
+        let mut encoder = png::Encoder::new(&mut file, 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 0, 0x80, 0x00]).unwrap();
+        writer.finish().unwrap();
+
+        let linear = decode_png(&file, true).unwrap();
+        assert_eq!(linear.texels[0], Vec3::ZERO);
+        assert_eq!(linear.texels[1], Vec3::splat(32768.0 / 65535.0));
+
+        let srgb = decode_png(&file, false).unwrap();
+        assert!((srgb.texels[1].x - 0.2140).abs() < 1e-3);
+    }
+}
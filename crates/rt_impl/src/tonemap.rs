@@ -0,0 +1,190 @@
+use spirv_std::glam::{mat3, vec3, Mat3, Vec3};
+
+use crate::{
+    color::{luminance, to_rec709},
+    ShaderConstants,
+};
+
+// values for `ShaderConstants::tonemap`.
+pub const TONEMAP_NONE: u32 = 0;
+pub const TONEMAP_REINHARD: u32 = 1;
+// reinhard that maps `ShaderConstants::white_point` to white instead of infinity.
+pub const TONEMAP_REINHARD_EXTENDED: u32 = 2;
+pub const TONEMAP_ACES: u32 = 3;
+pub const TONEMAP_AGX: u32 = 4;
+pub const TONEMAP_HABLE: u32 = 5;
+
+/// Scales linear working space radiance by `sc.exposure` stops and compresses it into
+/// [0, 1] with the selected operator. The operators are fit to rec.709, so the result is
+/// linear rec.709, the output transform comes after.
+pub fn tonemap(sc: &ShaderConstants, color: Vec3) -> Vec3 {
+    let c = to_rec709(sc, color).max(Vec3::ZERO) * sc.exposure.exp2();
+
+    match sc.tonemap {
+        TONEMAP_REINHARD => reinhard(c),
+        TONEMAP_REINHARD_EXTENDED => reinhard_extended(c, sc.white_point),
+        TONEMAP_ACES => aces_fitted(c),
+        TONEMAP_AGX => agx(c),
+        TONEMAP_HABLE => hable(c),
+        _ => c,
+    }
+}
+
+// scales by luminance so the hue survives.
+fn reinhard(c: Vec3) -> Vec3 {
+    let l = luminance(c);
+    if l <= 0.0 {
+        return c;
+    }
+    (c * (1.0 / (1.0 + l))).min(Vec3::ONE)
+}
+
+fn reinhard_extended(c: Vec3, white_point: f32) -> Vec3 {
+    let l = luminance(c);
+    if l <= 0.0 {
+        return c;
+    }
+
+    let w2 = (white_point * white_point).max(1e-6);
+    let mapped = l * (1.0 + l / w2) / (1.0 + l);
+    (c * (mapped / l)).min(Vec3::ONE)
+}
+
+// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+fn aces_fitted(c: Vec3) -> Vec3 {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT, column major.
+    let input = mat3(
+        vec3(0.59719, 0.07600, 0.02840),
+        vec3(0.35458, 0.90834, 0.13383),
+        vec3(0.04823, 0.01566, 0.83777),
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let output = mat3(
+        vec3(1.60475, -0.10208, -0.00327),
+        vec3(-0.53108, 1.10813, -0.07276),
+        vec3(-0.07367, -0.00605, 1.07602),
+    );
+
+    let v = input * c;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+    (output * (a / b)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// minimal AgX by Benjamin Wrensch, a polynomial fit of the base contrast curve.
+fn agx(c: Vec3) -> Vec3 {
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let inset: Mat3 = mat3(
+        vec3(0.8424791, 0.04232824, 0.04237565),
+        vec3(0.0784336, 0.8784686, 0.0784336),
+        vec3(0.07922375, 0.07916613, 0.879143),
+    );
+    let outset: Mat3 = mat3(
+        vec3(1.196879, -0.05289685, -0.05297164),
+        vec3(-0.09802088, 1.151903, -0.09804345),
+        vec3(-0.09902974, -0.09896118, 1.151074),
+    );
+
+    let v = (inset * c).max(Vec3::splat(1e-10));
+    let v = (vec3(v.x.log2(), v.y.log2(), v.z.log2()) - MIN_EV) / (MAX_EV - MIN_EV);
+    let x = v.clamp(Vec3::ZERO, Vec3::ONE);
+
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+
+    // the curve gives display encoded values, undo the 2.2 gamma it assumes.
+    let linear = (outset * curve).max(Vec3::ZERO);
+    vec3(linear.x.powf(2.2), linear.y.powf(2.2), linear.z.powf(2.2)).min(Vec3::ONE)
+}
+
+// John Hable's filmic curve from Uncharted 2.
+fn hable(c: Vec3) -> Vec3 {
+    fn partial(x: Vec3) -> Vec3 {
+        let (a, b, cc, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        ((x * (a * x + cc * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+
+    const EXPOSURE_BIAS: f32 = 2.0;
+    const WHITE: f32 = 11.2;
+
+    (partial(c * EXPOSURE_BIAS) / partial(Vec3::splat(WHITE))).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{output_transform, WORKING_ACESCG, WORKING_REC709};
+
+    #[test]
+    fn test_operators_are_monotonic_and_bounded() {
+        for op in [
+            TONEMAP_REINHARD,
+            TONEMAP_REINHARD_EXTENDED,
+            TONEMAP_ACES,
+            TONEMAP_AGX,
+            TONEMAP_HABLE,
+        ] {
+            let sc = ShaderConstants {
+                tonemap: op,
+                ..Default::default()
+            };
+
+            assert!(tonemap(&sc, Vec3::ZERO).max_element() < 1e-3);
+
+            let mut last = -1.0;
+            for i in 0..200 {
+                let v = tonemap(&sc, Vec3::splat(1.1f32.powi(i) * 1e-3));
+                assert!(v.max_element() <= 1.0);
+                assert!(v.x >= last - 1e-6, "operator {op} isn't monotonic");
+                last = v.x;
+            }
+
+            // saturates far above the white point.
+            assert!(last > 0.9, "operator {op} never reaches white");
+        }
+    }
+
+    #[test]
+    fn test_exposure() {
+        let sc = ShaderConstants {
+            tonemap: TONEMAP_NONE,
+            exposure: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(tonemap(&sc, vec3(0.25, 0.5, 0.1)), vec3(0.5, 1.0, 0.2));
+    }
+
+    #[test]
+    fn test_working_space_doesnt_change_the_result() {
+        for op in [
+            TONEMAP_NONE,
+            TONEMAP_REINHARD,
+            TONEMAP_REINHARD_EXTENDED,
+            TONEMAP_ACES,
+            TONEMAP_AGX,
+            TONEMAP_HABLE,
+        ] {
+            let sc = |working_space| ShaderConstants {
+                tonemap: op,
+                working_space,
+                ..Default::default()
+            };
+            let (rec709, acescg) = (sc(WORKING_REC709), sc(WORKING_ACESCG));
+
+            // a grey ramp and an orange one, given in ACEScg and as the same colors in rec.709.
+            for c in [Vec3::ONE, vec3(0.8, 0.3, 0.05)] {
+                for i in 0..100 {
+                    let c = c * 1.1f32.powi(i) * 1e-3;
+                    let a = output_transform(&rec709, tonemap(&rec709, to_rec709(&acescg, c)));
+                    let b = output_transform(&acescg, tonemap(&acescg, c));
+                    assert!(a.distance(b) < 1e-4, "operator {op} gives {a} and {b}");
+                }
+            }
+        }
+    }
+}
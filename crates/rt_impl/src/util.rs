@@ -57,8 +57,9 @@ pub fn hash32(p: Vec2) -> Vec3 {
 //
 //     return vec3(x, y, z);
 // }
-pub fn random_in_unit_sphere(p: Vec2) -> Vec3 {
-    let rand = vec3(rand_f32(p.x), rand_f32(p.y), rand_f32(p.x));
+// `u` is uniform in [0, 1)^3.
+pub fn random_in_unit_sphere(u: Vec3) -> Vec3 {
+    let rand = u;
     let phi = 2.0 * PI * rand.x;
     let cos_theta = 2.0 * rand.y - 1.0;
     let u = rand.z;
@@ -73,8 +74,8 @@ pub fn random_in_unit_sphere(p: Vec2) -> Vec3 {
     Vec3::new(x, y, z)
 }
 
-pub fn random_on_hemisphere(normal: Vec3, seed: Vec2) -> Vec3 {
-    let rd = random_in_unit_sphere(seed); // random vector from 0.0, 1.0
+pub fn random_on_hemisphere(normal: Vec3, u: Vec3) -> Vec3 {
+    let rd = random_in_unit_sphere(u);
     let res = rd + normal;
 
     if res.abs() == Vec3::splat(0.0) {
@@ -84,6 +85,37 @@ pub fn random_on_hemisphere(normal: Vec3, seed: Vec2) -> Vec3 {
     }
 }
 
+// cosine weighted direction around `normal`, pdf is cos(theta) / pi.
+pub fn cosine_on_hemisphere(normal: Vec3, u: Vec2) -> Vec3 {
+    let (u1, u2) = (u.x, u.y);
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+
+    let (t, b) = normal.any_orthonormal_pair();
+    (t * (r * phi.cos()) + b * (r * phi.sin()) + normal * (1.0 - u1).max(0.0).sqrt()).normalize()
+}
+
+// direction around `axis` distributed as cos(alpha)^n, pdf is (n + 1) / 2pi * cos(alpha)^n.
+pub fn phong_lobe(axis: Vec3, n: f32, u: Vec2) -> Vec3 {
+    let (u1, u2) = (u.x, u.y);
+    let cos_a = u1.powf(1.0 / (n + 1.0));
+    let sin_a = (1.0 - cos_a * cos_a).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+
+    let (t, b) = axis.any_orthonormal_pair();
+    (t * (sin_a * phi.cos()) + b * (sin_a * phi.sin()) + axis * cos_a).normalize()
+}
+
+// weight for combining two sampling strategies, Veach's power heuristic with beta = 2.
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    let (a, b) = (pdf_a * pdf_a, pdf_b * pdf_b);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * v.dot(n) * n
 }
@@ -112,7 +144,7 @@ pub fn cosine_similarity(a: Vec3, b: Vec3) -> f32 {
 
 // Since we plan on running this in the gpu we cannot use any standard rust random libs.
 // We will be using Bob Jenkins' smallprng for this.
-fn hash(x: u32) -> u32 {
+pub fn hash(x: u32) -> u32 {
     let mut x = x;
     x += x << 10;
     x ^= x >> 6;
@@ -153,13 +185,38 @@ pub fn rand_vec3(x: Vec3) -> Vec3 {
     vec3(rand_f32(x.x), rand_f32(x.y), rand_f32(x.z))
 }
 
-pub fn disk_point(radius: f32, seed: Vec2) -> Vec2 {
-    let (x1, x2) = (rand_f32(seed.x), rand_f32(seed.y));
+pub fn disk_point(radius: f32, u: Vec2) -> Vec2 {
+    let (x1, x2) = (u.x, u.y);
     let p = radius * (1.0 - x1).sqrt();
     let theta = x2 * 2.0 * PI;
     vec2(p * theta.cos(), p * theta.sin())
 }
 
+/// Directory for the files a test reads, unique to the test and the process and removed with
+/// everything in it when dropped.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rt_impl_{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn join(&self, file: &str) -> std::path::PathBuf {
+        self.0.join(file)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,6 +1,280 @@
-use rt_cpu::render_cpu;
-use spirv_std::glam::uvec2;
+use std::{path::PathBuf, process::exit, time::Duration};
+
+use clap::{Parser, ValueEnum};
+use rt_cpu::{render_progressive, ExrPixelType, Image, ImageFormat, ProgressiveSettings};
+use rt_impl::{
+    aov::Aov,
+    camera::Camera,
+    denoise::DENOISE_ATROUS,
+    depth::DOF_THIN_LENS,
+    describe_cornell_box, describe_scene, describe_scene2,
+    gltf::load_gltf,
+    modes::{Display, Dof, Filter, Sampler, ToneMap, WorkingSpace},
+    obj::load_obj,
+    scene_file::load_scene_file,
+    Scene, ShaderConstants,
+};
+
+#[derive(Parser)]
+#[command(about = "Render a scene on the cpu")]
+struct Args {
+    /// [default: 1920]
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// [default: 1080]
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// samples per pixel [default: 100]
+    #[arg(long)]
+    spp: Option<u32>,
+
+    /// maximum number of bounces per path [default: 100]
+    #[arg(long)]
+    bounces: Option<i32>,
+
+    /// `scene`, `scene2`, `cornell` or the path to a .toml, .obj, .gltf or .glb file.
+    /// Settings in a .toml scene are used unless overridden here.
+    #[arg(long, default_value = "scene")]
+    scene: String,
+
+    #[arg(short, long, default_value = "output.png")]
+    output: PathBuf,
+
+    /// defaults to the extension of the output path
+    #[arg(long)]
+    format: Option<Format>,
+
+    /// worker threads, defaults to one per core
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// [default: 0]
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// [default: post]
+    #[arg(long, value_enum)]
+    dof: Option<Dof>,
+
+    /// [default: none]
+    #[arg(long, value_enum)]
+    tonemap: Option<ToneMap>,
+
+    /// exposure adjustment in stops, applied before tone mapping [default: 0]
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_stops)]
+    exposure: Option<f32>,
+
+    /// primaries of the scene's colors and of exr output [default: rec709]
+    #[arg(long, value_enum)]
+    working_space: Option<WorkingSpace>,
+
+    /// how png and ppm output is encoded [default: srgb]
+    #[arg(long, value_enum)]
+    display: Option<Display>,
+
+    /// extra buffers to write, any of normal, albedo, depth, position, material_id,
+    /// object_id and samples. Exr output stores them as layers, other formats as
+    /// separate files next to the output.
+    #[arg(long, value_delimiter = ',', value_parser = parse_aov)]
+    aov: Vec<Aov>,
+
+    /// lens diameter used with `--dof lens`, defaults to the scene's camera or 0.1
+    #[arg(long)]
+    aperture: Option<f32>,
+
+    /// how the random numbers of a pixel's samples are placed [default: sobol]
+    #[arg(long, value_enum)]
+    sampler: Option<Sampler>,
+
+    /// how samples are weighted into the pixels around them [default: box]
+    #[arg(long, value_enum)]
+    filter: Option<Filter>,
+
+    /// filter the noise out of the radiance, guided by the normal, albedo and position
+    #[arg(long)]
+    denoise: bool,
+
+    /// samples added to every pixel per progressive pass
+    #[arg(long, default_value_t = 4)]
+    pass_spp: u32,
+
+    /// stop once the mean relative noise of the pixels drops to this, e.g. 0.01
+    #[arg(long)]
+    target_noise: Option<f32>,
+
+    /// stop after the pass that crosses this many seconds
+    #[arg(long, value_parser = parse_seconds)]
+    time_limit: Option<Duration>,
+
+    /// write the image after every pass instead of only at the end
+    #[arg(long)]
+    write_passes: bool,
+
+    /// adaptive sampling, pixels stop once their relative noise is at or below this.
+    /// `--spp` is the most a pixel can take.
+    #[arg(long)]
+    adaptive: Option<f32>,
+
+    /// samples every pixel takes before adaptive sampling can stop it
+    #[arg(long, default_value_t = 16)]
+    min_spp: u32,
+}
+
+fn parse_aov(name: &str) -> Result<Aov, String> {
+    Aov::from_name(name).ok_or_else(|| format!("unknown aov `{name}`"))
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    // also rules out nan, infinity and values too big for a duration.
+    match Duration::try_from_secs_f64(secs) {
+        Ok(d) if secs > 0.0 => Ok(d),
+        _ => Err(format!("`{s}` isn't a positive number of seconds")),
+    }
+}
+
+fn parse_stops(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(stops) if stops.is_finite() => Ok(stops),
+        _ => Err(format!("`{s}` isn't a finite number of stops")),
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    Png,
+    Ppm,
+    /// 16 bit float openexr
+    Exr,
+    /// 32 bit float openexr
+    ExrFloat,
+}
+
+// scene and the settings to render it with before the command line is applied.
+fn load_scene(name: &str) -> Result<(Scene, ShaderConstants), String> {
+    let c = ShaderConstants::default();
+
+    let scene = match name {
+        "scene" => (Scene::new(describe_scene(), Camera::default()), c),
+        "scene2" => (Scene::new(describe_scene2(), Camera::default()), c),
+        "cornell" => (
+            Scene::new(describe_cornell_box(), Camera::default()),
+            ShaderConstants { sky: 0, ..c },
+        ),
+        path if path.ends_with(".toml") => load_scene_file(path).map_err(|e| e.to_string())?,
+        path if path.ends_with(".obj") => {
+            let world = load_obj(path).map_err(|e| e.to_string())?;
+            (Scene::new(world, Camera::default()), c)
+        }
+        path if path.ends_with(".gltf") || path.ends_with(".glb") => {
+            let gltf = load_gltf(path).map_err(|e| e.to_string())?;
+            let camera = gltf
+                .cameras
+                .first()
+                .map_or(Camera::default(), |c| (*c).into());
+            (Scene::new(gltf.world, camera), c)
+        }
+        _ => return Err(format!("unknown scene `{name}`")),
+    };
+
+    Ok(scene)
+}
 
 pub fn main() {
-    render_cpu(uvec2(1920, 1080));
+    let args = Args::parse();
+
+    if let Some(n) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+            .expect("thread pool is only configured once");
+    }
+
+    let format = match args.format {
+        Some(Format::Png) => ImageFormat::Png,
+        Some(Format::Ppm) => ImageFormat::Ppm,
+        Some(Format::Exr) => ImageFormat::Exr(ExrPixelType::Half),
+        Some(Format::ExrFloat) => ImageFormat::Exr(ExrPixelType::Float),
+        None => ImageFormat::from_path(&args.output).unwrap_or_else(|| {
+            eprintln!(
+                "can't tell the format of {}, pass --format",
+                args.output.display()
+            );
+            exit(2);
+        }),
+    };
+
+    let (mut scene, mut c) = load_scene(&args.scene).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+
+    c.width = args.width.unwrap_or(c.width);
+    c.height = args.height.unwrap_or(c.height);
+    c.aa_stages = args.spp.unwrap_or(c.aa_stages);
+    c.bounce_limit = args.bounces.unwrap_or(c.bounce_limit);
+    c.seed = args.seed.unwrap_or(c.seed);
+
+    c.dof = args.dof.map_or(c.dof, Dof::mode);
+    c.tonemap = args.tonemap.map_or(c.tonemap, ToneMap::mode);
+    c.exposure = args.exposure.unwrap_or(c.exposure);
+    if args.denoise {
+        c.denoise = DENOISE_ATROUS;
+    }
+
+    c.sampler = args.sampler.map_or(c.sampler, Sampler::mode);
+    c.filter = args.filter.map_or(c.filter, Filter::mode);
+    c.working_space = args
+        .working_space
+        .map_or(c.working_space, WorkingSpace::mode);
+    c.display = args.display.map_or(c.display, Display::mode);
+
+    if let Some(aperture) = args.aperture {
+        scene.camera.aperture = aperture;
+    } else if c.dof == DOF_THIN_LENS && scene.camera.aperture == 0.0 {
+        scene.camera.aperture = 0.1;
+    }
+
+    println!(
+        "Rendering on CPU with width, height: {}, {}",
+        c.width, c.height
+    );
+
+    let save = |image: &Image| {
+        if let Err(e) = image.save(&args.output, format) {
+            eprintln!("failed to write {}: {e}", args.output.display());
+            exit(1);
+        }
+    };
+
+    let settings = ProgressiveSettings {
+        pass_samples: args.pass_spp,
+        target_noise: args.target_noise,
+        time_limit: args.time_limit,
+        adaptive_threshold: args.adaptive,
+        min_samples: args.min_spp,
+    };
+    let image = render_progressive(&scene, &c, &args.aov, &settings, |r| {
+        let p = r.progress();
+        eprintln!(
+            "pass {}: {} spp, {:.1} mean, {} active, noise {:.4}, {:.1}s",
+            p.passes,
+            p.samples,
+            p.mean_samples,
+            p.active,
+            p.noise,
+            p.elapsed.as_secs_f32()
+        );
+        if args.write_passes {
+            save(&r.image());
+        }
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+
+    save(&image);
 }